@@ -3,10 +3,11 @@ use std::fmt::Formatter;
 use argon2::Error as ArgonError;
 use reqwest::Error as ReqwestError;
 use reqwest_middleware::Error as MiddlewareReqwestError;
+use serde::Serialize;
 use tracing::{event, instrument, Level};
 use warp::{
-    body::BodyDeserializeError, cors::CorsForbidden, http::StatusCode, reject::Reject, Rejection,
-    Reply,
+    body::BodyDeserializeError, cors::CorsForbidden, http::StatusCode, reject::Reject,
+    reply::Response, Rejection, Reply,
 };
 
 #[derive(Debug)]
@@ -15,7 +16,21 @@ pub enum Error {
     MissingParameters,
     WrongPassword,
     CannotDecryptToken,
+    InvalidRefreshToken,
+    InvalidTwoFactorCode,
+    InvalidCsrfToken,
+    KeyringError(String),
+    ConfigError(String),
+    RegistrationClosed,
     Unauthorized,
+    InsufficientScope,
+    QuestionNotFound,
+    AnswerNotFound,
+    OrganizationNotFound,
+    OidcError(String),
+    TooManyRequests(u64),
+    AccountLocked(u64),
+    ValidationError(Vec<FieldError>),
     ArgonLibraryError(ArgonError),
     InvalidRange,
     DataBaseQueryError(sqlx::Error),
@@ -24,6 +39,8 @@ pub enum Error {
     MiddlewareReqwestAPIError(MiddlewareReqwestError),
     ClientError(APILayerError),
     ServerError(APILayerError),
+    ProfanityFilterUnavailable,
+    RescanInProgress,
 }
 
 #[derive(Debug, Clone)]
@@ -32,6 +49,13 @@ pub struct APILayerError {
     pub message: String,
 }
 
+/// A rejected field of a request body, returned to the client as is.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
 impl std::fmt::Display for APILayerError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "Status: {}, Message: {}", self.status, self.message)
@@ -45,7 +69,30 @@ impl std::fmt::Display for Error {
             Error::MissingParameters => write!(f, "Missing parameter"),
             Error::WrongPassword => write!(f, "Wrong password"),
             Error::CannotDecryptToken => write!(f, "Cannot decrypt token"),
+            Error::InvalidRefreshToken => write!(f, "Invalid refresh token"),
+            Error::InvalidTwoFactorCode => write!(f, "Invalid two-factor code"),
+            Error::InvalidCsrfToken => write!(f, "Missing or invalid CSRF token"),
+            Error::KeyringError(ref err) => write!(f, "Invalid key configuration: {}", err),
+            Error::ConfigError(ref err) => write!(f, "Invalid configuration: {}", err),
+            Error::RegistrationClosed => write!(f, "Registration is closed"),
             Error::Unauthorized => write!(f, "Unauthorized to change the resource"),
+            Error::InsufficientScope => write!(f, "API key lacks the required scope"),
+            Error::QuestionNotFound => write!(f, "Question not found"),
+            Error::AnswerNotFound => write!(f, "Answer not found"),
+            Error::OrganizationNotFound => write!(f, "Organization not found"),
+            Error::OidcError(ref err) => write!(f, "OpenID Connect error: {}", err),
+            Error::TooManyRequests(secs) => write!(f, "Too many requests, retry in {}s", secs),
+            Error::AccountLocked(secs) => write!(f, "Account locked, retry in {}s", secs),
+            Error::ValidationError(ref errors) => {
+                write!(f, "Invalid fields: ")?;
+                for (i, err) in errors.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", err.field, err.message)?;
+                }
+                Ok(())
+            }
             Error::ArgonLibraryError(_) => write!(f, "Cannot verify password"),
             Error::InvalidRange => write!(f, "Invalid range"),
             Error::DataBaseQueryError(_) => write!(f, "Cannot update, invalid data."),
@@ -54,6 +101,8 @@ impl std::fmt::Display for Error {
             Error::MiddlewareReqwestAPIError(ref err) => write!(f, "External API error: {}", err),
             Error::ClientError(ref err) => write!(f, "External Client error: {}", err),
             Error::ServerError(ref err) => write!(f, "External Server error: {}", err),
+            Error::ProfanityFilterUnavailable => write!(f, "Profanity filter unavailable"),
+            Error::RescanInProgress => write!(f, "A re-scan is already running"),
         }
     }
 }
@@ -64,7 +113,7 @@ impl Reject for APILayerError {}
 const DUPLICATE_KEY: u32 = 23505;
 
 #[instrument]
-pub async fn return_error(r: Rejection) -> Result<impl Reply, Rejection> {
+pub async fn return_error(r: Rejection) -> Result<Response, Rejection> {
     if let Some(crate::Error::DataBaseQueryError(err)) = r.find() {
         event!(Level::ERROR, "Database query error");
         match err {
@@ -73,72 +122,182 @@ pub async fn return_error(r: Rejection) -> Result<impl Reply, Rejection> {
                     Ok(warp::reply::with_status(
                         "Account already exists".to_string(),
                         StatusCode::UNPROCESSABLE_ENTITY,
-                    ))
+                    )
+                    .into_response())
                 } else {
                     Ok(warp::reply::with_status(
                         "Cannot update data".to_string(),
                         StatusCode::UNPROCESSABLE_ENTITY,
-                    ))
+                    )
+                    .into_response())
                 }
             }
             _ => Ok(warp::reply::with_status(
                 "Cannot update data".to_string(),
                 StatusCode::UNPROCESSABLE_ENTITY,
-            )),
+            )
+            .into_response()),
         }
     } else if let Some(crate::Error::ReqwestAPIError(err)) = r.find() {
         event!(Level::ERROR, "{err}");
         Ok(warp::reply::with_status(
             "Internal Server Error".to_string(),
             StatusCode::INTERNAL_SERVER_ERROR,
-        ))
+        )
+        .into_response())
     } else if let Some(crate::Error::Unauthorized) = r.find() {
         event!(Level::ERROR, "Not matching account id");
         Ok(warp::reply::with_status(
             "Unauthorized to change the resource".to_string(),
             StatusCode::UNAUTHORIZED,
-        ))
+        )
+        .into_response())
+    } else if let Some(crate::Error::InsufficientScope) = r.find() {
+        event!(Level::ERROR, "API key used outside its scopes");
+        Ok(warp::reply::with_status(
+            "API key lacks the required scope".to_string(),
+            StatusCode::FORBIDDEN,
+        )
+        .into_response())
+    } else if let Some(crate::Error::RegistrationClosed) = r.find() {
+        event!(Level::WARN, "Registration attempt while sign-up is closed");
+        Ok(
+            warp::reply::with_status("Registration is closed".to_string(), StatusCode::FORBIDDEN)
+                .into_response(),
+        )
+    } else if let Some(crate::Error::InvalidCsrfToken) = r.find() {
+        event!(Level::WARN, "Cookie session without matching CSRF token");
+        Ok(warp::reply::with_status(
+            "Missing or invalid CSRF token".to_string(),
+            StatusCode::FORBIDDEN,
+        )
+        .into_response())
+    } else if let Some(crate::Error::QuestionNotFound) = r.find() {
+        event!(Level::WARN, "Question missing or not visible");
+        Ok(
+            warp::reply::with_status("Question not found".to_string(), StatusCode::NOT_FOUND)
+                .into_response(),
+        )
+    } else if let Some(crate::Error::AnswerNotFound) = r.find() {
+        event!(Level::WARN, "Answer missing");
+        Ok(
+            warp::reply::with_status("Answer not found".to_string(), StatusCode::NOT_FOUND)
+                .into_response(),
+        )
+    } else if let Some(crate::Error::OrganizationNotFound) = r.find() {
+        event!(Level::WARN, "Organization missing or not a member");
+        Ok(
+            warp::reply::with_status("Organization not found".to_string(), StatusCode::NOT_FOUND)
+                .into_response(),
+        )
+    } else if let Some(crate::Error::OidcError(err)) = r.find() {
+        event!(Level::ERROR, "{err}");
+        Ok(warp::reply::with_status(
+            "Single sign-on failed".to_string(),
+            StatusCode::UNAUTHORIZED,
+        )
+        .into_response())
+    } else if let Some(crate::Error::TooManyRequests(retry_after)) = r.find() {
+        event!(Level::WARN, "Rate limit hit, retry in {retry_after}s");
+        Ok(warp::reply::with_header(
+            warp::reply::with_status(
+                "Too many requests".to_string(),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            "Retry-After",
+            retry_after.to_string(),
+        )
+        .into_response())
+    } else if let Some(crate::Error::AccountLocked(retry_after)) = r.find() {
+        event!(Level::WARN, "Login to locked account");
+        Ok(warp::reply::with_header(
+            warp::reply::with_status(
+                "Account temporarily locked".to_string(),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            "Retry-After",
+            retry_after.to_string(),
+        )
+        .into_response())
+    } else if let Some(crate::Error::ValidationError(errors)) = r.find() {
+        event!(Level::WARN, "Request failed validation");
+        Ok(
+            warp::reply::with_status(warp::reply::json(errors), StatusCode::UNPROCESSABLE_ENTITY)
+                .into_response(),
+        )
     } else if let Some(crate::Error::WrongPassword) = r.find() {
         event!(Level::ERROR, "Entered wrong password");
         Ok(warp::reply::with_status(
             "Wrong E-mail/Password combination".to_string(),
             StatusCode::UNAUTHORIZED,
-        ))
+        )
+        .into_response())
+    } else if let Some(crate::Error::InvalidRefreshToken) = r.find() {
+        event!(Level::ERROR, "Invalid, expired or reused refresh token");
+        Ok(warp::reply::with_status(
+            "Invalid refresh token".to_string(),
+            StatusCode::UNAUTHORIZED,
+        )
+        .into_response())
+    } else if let Some(crate::Error::InvalidTwoFactorCode) = r.find() {
+        event!(Level::ERROR, "Invalid two-factor code or expired challenge");
+        Ok(warp::reply::with_status(
+            "Invalid two-factor code".to_string(),
+            StatusCode::UNAUTHORIZED,
+        )
+        .into_response())
     } else if let Some(crate::Error::MiddlewareReqwestAPIError(err)) = r.find() {
         event!(Level::ERROR, "{err}");
         Ok(warp::reply::with_status(
             "Internal Server Error".to_string(),
             StatusCode::INTERNAL_SERVER_ERROR,
-        ))
+        )
+        .into_response())
     } else if let Some(crate::Error::ClientError(err)) = r.find() {
         event!(Level::ERROR, "{err}");
         Ok(warp::reply::with_status(
             "Internal Server Error".to_string(),
             StatusCode::INTERNAL_SERVER_ERROR,
-        ))
+        )
+        .into_response())
     } else if let Some(crate::Error::ServerError(err)) = r.find() {
         event!(Level::ERROR, "{err}");
         Ok(warp::reply::with_status(
             "Internal Server Error".to_string(),
             StatusCode::INTERNAL_SERVER_ERROR,
-        ))
+        )
+        .into_response())
+    } else if let Some(crate::Error::ProfanityFilterUnavailable) = r.find() {
+        event!(
+            Level::ERROR,
+            "Write refused while the profanity filter is unavailable"
+        );
+        Ok(warp::reply::with_status(
+            "Content filter unavailable, try again later".to_string(),
+            StatusCode::SERVICE_UNAVAILABLE,
+        )
+        .into_response())
+    } else if let Some(crate::Error::RescanInProgress) = r.find() {
+        event!(Level::WARN, "Re-scan requested while one is running");
+        Ok(warp::reply::with_status(
+            "A re-scan is already running".to_string(),
+            StatusCode::CONFLICT,
+        )
+        .into_response())
     } else if let Some(error) = r.find::<BodyDeserializeError>() {
         event!(Level::ERROR, "Cannot deserialize request body: {}", error);
-        Ok(warp::reply::with_status(
-            error.to_string(),
-            StatusCode::UNPROCESSABLE_ENTITY,
-        ))
+        Ok(
+            warp::reply::with_status(error.to_string(), StatusCode::UNPROCESSABLE_ENTITY)
+                .into_response(),
+        )
     } else if let Some(error) = r.find::<CorsForbidden>() {
         event!(Level::ERROR, "CORS forbidded error {}", error);
-        Ok(warp::reply::with_status(
-            error.to_string(),
-            StatusCode::FORBIDDEN,
-        ))
+        Ok(warp::reply::with_status(error.to_string(), StatusCode::FORBIDDEN).into_response())
     } else {
         event!(Level::WARN, "Request route was not found");
-        Ok(warp::reply::with_status(
-            "Route not found".to_string(),
-            StatusCode::NOT_FOUND,
-        ))
+        Ok(
+            warp::reply::with_status("Route not found".to_string(), StatusCode::NOT_FOUND)
+                .into_response(),
+        )
     }
 }
@@ -6,6 +6,7 @@ use futures_util::future::FutureExt;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use mock_server::MockServer;
 use qna::{config, handle_errors, oneshot, setup_store};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -29,12 +30,21 @@ struct QuestionAnswer {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct Token(String);
+struct Token {
+    access_token: String,
+    refresh_token: String,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), handle_errors::Error> {
-    // Load config.
+    // Load config. SSO logins go against the mock identity provider.
     dotenv::dotenv().ok();
+    std::env::set_var("OIDC_ISSUER", "http://127.0.0.1:3031");
+    std::env::set_var("OIDC_CLIENT_ID", "qna");
+    std::env::set_var("OIDC_CLIENT_SECRET", "secret");
+    std::env::set_var("OIDC_REDIRECT_URL", "http://localhost:3030/oidc/callback");
+    // Check posts as they are written, so they are listed right away.
+    std::env::set_var("MODERATION_MODE", "inline");
     let config = config::Config::new().expect("Config can't be set");
 
     // Run sqlx commands to manage db.
@@ -67,12 +77,14 @@ async fn main() -> Result<(), handle_errors::Error> {
     let store = setup_store(&config).await?;
 
     // Start the server and listen for a sender signal to shut it down.
-    let handler = oneshot(store).await;
+    let handler = oneshot(&config, store).await?;
+    let idp_socket = "127.0.0.1:3031".parse().expect("Not a valid address");
+    let idp_handler = MockServer::new(idp_socket).oneshot();
 
     // Create a test user.
     let u = User {
         email: "test@email.com".into(),
-        password: "password".into(),
+        password: "correct horse battery".into(),
     };
 
     let token;
@@ -89,6 +101,30 @@ async fn main() -> Result<(), handle_errors::Error> {
         }
     }
 
+    print!("Running register_weak_password...");
+    match std::panic::AssertUnwindSafe(register_weak_password())
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running login_unknown_email...");
+    match std::panic::AssertUnwindSafe(login_unknown_email())
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
     print!("Running login...");
     match std::panic::AssertUnwindSafe(login(u)).catch_unwind().await {
         Ok(t) => {
@@ -102,7 +138,43 @@ async fn main() -> Result<(), handle_errors::Error> {
     }
 
     print!("Running post_question...");
-    match std::panic::AssertUnwindSafe(post_question(token))
+    match std::panic::AssertUnwindSafe(post_question(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running post_question_with_api_key...");
+    match std::panic::AssertUnwindSafe(post_question_with_api_key(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running private_question...");
+    match std::panic::AssertUnwindSafe(private_question(token.clone()))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running login_history...");
+    match std::panic::AssertUnwindSafe(login_history(token.clone()))
         .catch_unwind()
         .await
     {
@@ -113,7 +185,33 @@ async fn main() -> Result<(), handle_errors::Error> {
         }
     }
 
+    print!("Running refresh_and_logout...");
+    match std::panic::AssertUnwindSafe(refresh_and_logout(token))
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
+    print!("Running oidc_login...");
+    match std::panic::AssertUnwindSafe(oidc_login())
+        .catch_unwind()
+        .await
+    {
+        Ok(_) => println!("✓"),
+        Err(_) => {
+            let _ = handler.sender.send(1);
+            let _ = idp_handler.sender.send(1);
+            std::process::exit(1);
+        }
+    }
+
     let _ = handler.sender.send(1);
+    let _ = idp_handler.sender.send(1);
 
     Ok(())
 }
@@ -130,6 +228,27 @@ async fn register_new_user(user: &User) {
         .await;
     assert_eq!(res.unwrap(), "Account added".to_string());
 }
+
+async fn register_weak_password() {
+    let user = User {
+        email: " New@Email.com".into(),
+        password: "password".into(),
+    };
+    let client = reqwest::Client::new();
+    let res = client
+        .post("http://localhost:3030/registration")
+        .json(&user)
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), 422);
+    assert_eq!(
+        res.json::<Value>().await.unwrap(),
+        serde_json::json!([{ "field": "password", "message": "is too short" }])
+    );
+}
+
 async fn login(user: User) -> Token {
     let client = reqwest::Client::new();
     let res = client
@@ -144,6 +263,27 @@ async fn login(user: User) -> Token {
     res.json::<Token>().await.unwrap()
 }
 
+async fn login_unknown_email() {
+    let user = User {
+        email: "nobody@email.com".into(),
+        password: "password".into(),
+    };
+    let client = reqwest::Client::new();
+    let res = client
+        .post("http://localhost:3030/login")
+        .json(&user)
+        .send()
+        .await
+        .unwrap();
+
+    // Same answer as a wrong password for a registered email.
+    assert_eq!(res.status(), 401);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "Wrong E-mail/Password combination"
+    );
+}
+
 async fn post_question(token: Token) {
     let q = Question {
         title: "First Question".to_string(),
@@ -153,7 +293,7 @@ async fn post_question(token: Token) {
     let client = reqwest::Client::new();
     let res = client
         .post("http://localhost:3030/questions")
-        .header("Authorization", token.0)
+        .header("Authorization", token.access_token)
         .json(&q)
         .send()
         .await
@@ -165,3 +305,199 @@ async fn post_question(token: Token) {
     assert_eq!(res.id, 1);
     assert_eq!(res.title, q.title);
 }
+
+async fn private_question(token: Token) {
+    let client = reqwest::Client::new();
+    let res = client
+        .post("http://localhost:3030/organizations")
+        .header("Authorization", token.access_token.clone())
+        .json(&serde_json::json!({ "name": "Team" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 201);
+    let organization_id = res.json::<Value>().await.unwrap()["id"].clone();
+
+    let res = client
+        .post("http://localhost:3030/questions")
+        .header("Authorization", token.access_token.clone())
+        .json(&serde_json::json!({
+            "title": "Team only",
+            "content": "Who can see this?",
+            "organization_id": organization_id,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let titles = |questions: Vec<QuestionAnswer>| -> Vec<String> {
+        questions.into_iter().map(|q| q.title).collect()
+    };
+    let anonymous = client
+        .get("http://localhost:3030/questions")
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<QuestionAnswer>>()
+        .await
+        .unwrap();
+    assert!(!titles(anonymous).contains(&"Team only".to_string()));
+
+    let member = client
+        .get("http://localhost:3030/questions")
+        .header("Authorization", token.access_token)
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<QuestionAnswer>>()
+        .await
+        .unwrap();
+    assert!(titles(member).contains(&"Team only".to_string()));
+}
+
+async fn refresh_and_logout(token: Token) {
+    let client = reqwest::Client::new();
+    let res = client
+        .post("http://localhost:3030/refresh")
+        .json(&serde_json::json!({ "refresh_token": token.refresh_token }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let rotated = res.json::<Token>().await.unwrap();
+
+    // The old refresh token was rotated out and can't be used again.
+    let res = client
+        .post("http://localhost:3030/refresh")
+        .json(&serde_json::json!({ "refresh_token": token.refresh_token }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 401);
+
+    let res = client
+        .post("http://localhost:3030/logout")
+        .header("Authorization", rotated.access_token.clone())
+        .json(&serde_json::json!({ "refresh_token": rotated.refresh_token }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    // The access token is on the denylist after logout.
+    let res = client
+        .post("http://localhost:3030/logout")
+        .header("Authorization", rotated.access_token)
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .unwrap();
+    assert_ne!(res.status(), 200);
+}
+
+async fn post_question_with_api_key(token: Token) {
+    let client = reqwest::Client::new();
+    let res = client
+        .post("http://localhost:3030/me/api-keys")
+        .header("Authorization", token.access_token.clone())
+        .json(&serde_json::json!({ "name": "ci", "scopes": ["answers:write"] }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 201);
+    let key = res.json::<Value>().await.unwrap()["key"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    // The key has no `questions:write` scope.
+    let q = Question {
+        title: "Automated question".to_string(),
+        content: "Posted by a bot".to_string(),
+    };
+    let res = client
+        .post("http://localhost:3030/questions")
+        .header("Authorization", key)
+        .json(&q)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 403);
+
+    let res = client
+        .get("http://localhost:3030/me/api-keys")
+        .header("Authorization", token.access_token)
+        .send()
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert!(!res[0]["last_used_on"].is_null());
+}
+
+async fn oidc_login() {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    // Follow the redirects by hand: service -> provider -> service callback.
+    let res = client
+        .get("http://localhost:3030/oidc/login")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 302);
+    let authorize = res.headers()["location"].to_str().unwrap().to_string();
+    // The state cookie is Secure, so it is sent by hand over plain http.
+    let state_cookie = res.headers()["set-cookie"]
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
+
+    let res = client.get(authorize).send().await.unwrap();
+    assert_eq!(res.status(), 302);
+    let callback = res.headers()["location"].to_str().unwrap().to_string();
+
+    // A callback from another browser is refused.
+    let res = client.get(&callback).send().await.unwrap();
+    assert_eq!(res.status(), 401);
+
+    let res = client
+        .get(&callback)
+        .header("Cookie", &state_cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let token = res.json::<Token>().await.unwrap();
+    assert!(!token.access_token.is_empty());
+
+    // The state is single use.
+    let res = client
+        .get(&callback)
+        .header("Cookie", &state_cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 401);
+}
+
+async fn login_history(token: Token) {
+    let client = reqwest::Client::new();
+    let res = client
+        .get("http://localhost:3030/me/logins")
+        .header("Authorization", token.access_token)
+        .send()
+        .await
+        .unwrap()
+        .json::<Value>()
+        .await
+        .unwrap();
+    assert_eq!(res[0]["success"], true);
+}
@@ -0,0 +1,2 @@
+ALTER TABLE accounts
+    ADD COLUMN token_version integer NOT NULL DEFAULT 0;
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS api_keys
+(
+    id           serial PRIMARY KEY,
+    account_id   integer      NOT NULL,
+    name         VARCHAR(255) NOT NULL,
+    prefix       VARCHAR(16)  NOT NULL,
+    key_hash     VARCHAR(64)  NOT NULL UNIQUE,
+    scopes       TEXT[]       NOT NULL,
+    revoked      BOOLEAN      NOT NULL DEFAULT FALSE,
+    created_on   TIMESTAMPTZ  NOT NULL DEFAULT NOW(),
+    last_used_on TIMESTAMPTZ
+);
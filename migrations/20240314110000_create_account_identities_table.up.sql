@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS account_identities
+(
+    id         serial PRIMARY KEY,
+    account_id integer      NOT NULL,
+    issuer     VARCHAR(255) NOT NULL,
+    subject    VARCHAR(255) NOT NULL,
+    created_on TIMESTAMP    NOT NULL DEFAULT NOW(),
+    UNIQUE (issuer, subject)
+);
+
+CREATE TABLE IF NOT EXISTS oidc_login_states
+(
+    state         VARCHAR(64)  PRIMARY KEY,
+    code_verifier VARCHAR(128) NOT NULL,
+    created_on    TIMESTAMPTZ  NOT NULL DEFAULT NOW()
+);
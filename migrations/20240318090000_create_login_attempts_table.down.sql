@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS login_lockouts;
+DROP TABLE IF EXISTS login_attempts;
+
+ALTER TABLE accounts
+    DROP COLUMN role;
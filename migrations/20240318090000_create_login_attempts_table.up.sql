@@ -0,0 +1,24 @@
+ALTER TABLE accounts
+    ADD COLUMN role VARCHAR(16) NOT NULL DEFAULT 'user';
+
+CREATE TABLE IF NOT EXISTS login_attempts
+(
+    id         serial PRIMARY KEY,
+    email      VARCHAR(255) NOT NULL,
+    account_id integer,
+    ip         VARCHAR(45),
+    success    BOOLEAN      NOT NULL,
+    created_on TIMESTAMPTZ  NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS login_attempts_account_id_idx ON login_attempts (account_id, created_on);
+CREATE INDEX IF NOT EXISTS login_attempts_ip_idx ON login_attempts (ip, created_on);
+
+-- Keyed by email rather than account id, so unknown emails lock the same way.
+CREATE TABLE IF NOT EXISTS login_lockouts
+(
+    email        VARCHAR(255) PRIMARY KEY,
+    failures     integer      NOT NULL DEFAULT 0,
+    locked_until TIMESTAMPTZ,
+    updated_on   TIMESTAMPTZ  NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,26 @@
+-- The secret is set on enrollment and only used for logins once a first
+-- code confirmed it. The last accepted time step keeps codes single-use.
+ALTER TABLE accounts
+    ADD COLUMN totp_secret    VARCHAR(64),
+    ADD COLUMN totp_enabled   BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN totp_last_step BIGINT;
+
+CREATE TABLE IF NOT EXISTS recovery_codes
+(
+    id         serial PRIMARY KEY,
+    account_id integer     NOT NULL,
+    code_hash  VARCHAR(64) NOT NULL,
+    used_on    TIMESTAMPTZ,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS recovery_codes_account_id_idx ON recovery_codes (account_id);
+
+-- Logins waiting for their second factor, keyed by the hashed challenge.
+CREATE TABLE IF NOT EXISTS login_challenges
+(
+    challenge_hash VARCHAR(64) PRIMARY KEY,
+    account_id     integer     NOT NULL,
+    attempts       integer     NOT NULL DEFAULT 0,
+    created_on     TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS invites
+(
+    id         serial PRIMARY KEY,
+    code_hash  VARCHAR(64) NOT NULL UNIQUE,
+    created_by integer     NOT NULL,
+    max_uses   integer     NOT NULL,
+    uses       integer     NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked    BOOLEAN     NOT NULL DEFAULT FALSE,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
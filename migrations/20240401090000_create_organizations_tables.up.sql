@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS organizations
+(
+    id         serial PRIMARY KEY,
+    name       VARCHAR(255) NOT NULL,
+    created_on TIMESTAMPTZ  NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS organization_members
+(
+    organization_id integer     NOT NULL REFERENCES organizations ON DELETE CASCADE,
+    account_id      integer     NOT NULL,
+    role            VARCHAR(16) NOT NULL DEFAULT 'member',
+    created_on      TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (organization_id, account_id)
+);
+
+CREATE INDEX IF NOT EXISTS organization_members_account_id_idx ON organization_members (account_id);
+
+-- Questions without an organization are public.
+ALTER TABLE questions
+    ADD COLUMN organization_id integer REFERENCES organizations;
@@ -0,0 +1,5 @@
+ALTER TABLE answers
+    DROP COLUMN moderation_status;
+
+ALTER TABLE questions
+    DROP COLUMN moderation_status;
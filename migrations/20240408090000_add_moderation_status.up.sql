@@ -0,0 +1,7 @@
+-- Posts written while the profanity filter was unavailable wait as
+-- 'pending' and are only shown to their author.
+ALTER TABLE questions
+    ADD COLUMN moderation_status VARCHAR(16) NOT NULL DEFAULT 'approved';
+
+ALTER TABLE answers
+    ADD COLUMN moderation_status VARCHAR(16) NOT NULL DEFAULT 'approved';
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS profanity_audits;
@@ -0,0 +1,17 @@
+-- Original text of every field the profanity filter changed, so moderators
+-- can check what was censored.
+CREATE TABLE IF NOT EXISTS profanity_audits
+(
+    id             serial PRIMARY KEY,
+    question_id    integer REFERENCES questions ON DELETE CASCADE,
+    answer_id      integer REFERENCES answers ON DELETE CASCADE,
+    field          VARCHAR(16) NOT NULL,
+    original       TEXT        NOT NULL,
+    censored       TEXT        NOT NULL,
+    bad_words      TEXT[]      NOT NULL,
+    bad_word_count integer     NOT NULL,
+    created_on     TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS profanity_audits_question_id_idx ON profanity_audits (question_id);
+CREATE INDEX IF NOT EXISTS profanity_audits_answer_id_idx ON profanity_audits (answer_id);
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS answers_pending_idx;
+DROP INDEX IF EXISTS questions_pending_idx;
@@ -0,0 +1,3 @@
+-- The moderation worker polls for pending posts.
+CREATE INDEX IF NOT EXISTS questions_pending_idx ON questions (id) WHERE moderation_status = 'pending';
+CREATE INDEX IF NOT EXISTS answers_pending_idx ON answers (id) WHERE moderation_status = 'pending';
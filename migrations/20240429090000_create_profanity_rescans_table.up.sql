@@ -0,0 +1,22 @@
+-- Re-scans of existing posts with the active profanity filter. The last
+-- ids scanned let an interrupted re-scan resume where it stopped.
+CREATE TABLE IF NOT EXISTS profanity_rescans
+(
+    id                serial PRIMARY KEY,
+    status            VARCHAR(16) NOT NULL DEFAULT 'running',
+    total_questions   BIGINT      NOT NULL,
+    total_answers     BIGINT      NOT NULL,
+    questions_scanned BIGINT      NOT NULL DEFAULT 0,
+    answers_scanned   BIGINT      NOT NULL DEFAULT 0,
+    questions_changed BIGINT      NOT NULL DEFAULT 0,
+    answers_changed   BIGINT      NOT NULL DEFAULT 0,
+    last_question_id  integer     NOT NULL DEFAULT 0,
+    last_answer_id    integer     NOT NULL DEFAULT 0,
+    error             TEXT,
+    started_on        TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_on        TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Only one re-scan runs at a time, whichever instance started it.
+CREATE UNIQUE INDEX IF NOT EXISTS profanity_rescans_running_idx
+    ON profanity_rescans (status) WHERE status = 'running';
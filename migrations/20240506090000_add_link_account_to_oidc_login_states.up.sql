@@ -0,0 +1,3 @@
+-- Account that started the login to link a new identity, if signed in.
+ALTER TABLE oidc_login_states
+    ADD COLUMN IF NOT EXISTS link_account_id integer REFERENCES accounts (id) ON DELETE CASCADE;
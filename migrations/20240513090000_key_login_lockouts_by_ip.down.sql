@@ -0,0 +1,10 @@
+DELETE FROM login_lockouts WHERE ip <> '';
+
+ALTER TABLE login_lockouts
+    DROP CONSTRAINT IF EXISTS login_lockouts_pkey;
+
+ALTER TABLE login_lockouts
+    ADD PRIMARY KEY (email);
+
+ALTER TABLE login_lockouts
+    DROP COLUMN IF EXISTS ip;
@@ -0,0 +1,10 @@
+-- Lockouts are kept per email and client IP, so failed logins from one
+-- client can't lock the owner of an email out everywhere.
+ALTER TABLE login_lockouts
+    ADD COLUMN IF NOT EXISTS ip VARCHAR(45) NOT NULL DEFAULT '';
+
+ALTER TABLE login_lockouts
+    DROP CONSTRAINT IF EXISTS login_lockouts_pkey;
+
+ALTER TABLE login_lockouts
+    ADD PRIMARY KEY (email, ip);
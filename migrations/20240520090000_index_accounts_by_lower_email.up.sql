@@ -0,0 +1,23 @@
+-- Emails are looked up case-insensitively, so accounts registered before
+-- emails were normalized still log in, and case variants can't register twice.
+--
+-- Accounts whose emails only differ in case were allowed before. They have to
+-- be merged or renamed by hand before upgrading; until then the migration
+-- stops and lists them.
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(email, ', ') INTO duplicates
+    FROM (
+        SELECT lower(email) AS email FROM accounts
+        GROUP BY lower(email)
+        HAVING COUNT(*) > 1
+    ) AS case_variants;
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'Accounts with emails differing only in case: %. Merge or rename them before upgrading.', duplicates;
+    END IF;
+END
+$$;
+
+CREATE UNIQUE INDEX IF NOT EXISTS accounts_email_lower_idx ON accounts (lower(email));
@@ -0,0 +1,5 @@
+ALTER TABLE answers
+    DROP COLUMN IF EXISTS moderation_claimed_on;
+
+ALTER TABLE questions
+    DROP COLUMN IF EXISTS moderation_claimed_on;
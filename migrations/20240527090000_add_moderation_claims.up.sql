@@ -0,0 +1,7 @@
+-- Every instance runs a moderation worker, so each claims the pending
+-- posts it checks until the claim expires.
+ALTER TABLE questions
+    ADD COLUMN IF NOT EXISTS moderation_claimed_on TIMESTAMP;
+
+ALTER TABLE answers
+    ADD COLUMN IF NOT EXISTS moderation_claimed_on TIMESTAMP;
@@ -0,0 +1 @@
+DELETE FROM login_lockouts WHERE email = '';
@@ -0,0 +1,3 @@
+-- Lockouts are kept per account, as (email, ''), and per client IP over all
+-- accounts, as ('', ip). Counters of an email from one IP are dropped.
+DELETE FROM login_lockouts WHERE email <> '' AND ip <> '';
@@ -0,0 +1,3 @@
+-- Nonce sent with the login, expected back in the provider's ID token.
+ALTER TABLE oidc_login_states
+    ADD COLUMN IF NOT EXISTS nonce VARCHAR(64) NOT NULL DEFAULT '';
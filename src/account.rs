@@ -1,11 +1,34 @@
+use std::str::FromStr;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::types::api_key::Scope;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Session {
     pub exp: DateTime<Utc>,
     pub account_id: AccountId,
     pub nbf: DateTime<Utc>,
+    pub jti: String,
+    pub token_version: i32,
+    /// Scopes of the API key the request was made with. Token sessions carry
+    /// no scopes and have full access to the account.
+    #[serde(default)]
+    pub scopes: Option<Vec<Scope>>,
+}
+
+impl Session {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.contains(&scope),
+            None => true,
+        }
+    }
+
+    pub fn is_api_key(&self) -> bool {
+        self.scopes.is_some()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -17,3 +40,116 @@ pub struct Account {
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AccountId(pub i32);
+
+/// Body of a sign-up. The invite code is only looked at in invite-only mode.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Registration {
+    #[serde(flatten)]
+    pub account: Account,
+    #[serde(default)]
+    pub invite_code: Option<String>,
+}
+
+/// Token pair handed out on login and on refresh.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tokens {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// Server-side record of an issued refresh token.
+#[derive(Debug, Clone)]
+pub struct RefreshToken {
+    pub id: i32,
+    pub account_id: AccountId,
+    pub expires_at: DateTime<Utc>,
+    pub revoked: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RefreshRequest {
+    /// Left out by browsers, which send the refresh token as a cookie.
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PasswordChange {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("Unknown role: {}", s)),
+        }
+    }
+}
+
+/// One entry of an account's login history.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoginAttempt {
+    pub ip: Option<String>,
+    pub success: bool,
+    pub created_on: DateTime<Utc>,
+}
+
+/// TOTP state of an account. A secret without `enabled` is an enrollment
+/// waiting for its first code.
+#[derive(Debug, Clone, Default)]
+pub struct TwoFactor {
+    pub secret: Option<String>,
+    pub enabled: bool,
+}
+
+/// Returned on TOTP enrollment, to be scanned into an authenticator app.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TwoFactorCode {
+    pub code: String,
+}
+
+/// Returned once when TOTP is enabled. Each code logs in a single time.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Returned by a login with the right password on an account with 2FA;
+/// exchanged for tokens together with a code.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoginChallenge {
+    pub challenge: String,
+}
+
+/// Second step of a 2FA login. The code is either a TOTP code or a
+/// recovery code.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChallengeResponse {
+    pub challenge: String,
+    pub code: String,
+}
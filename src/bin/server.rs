@@ -10,7 +10,5 @@ async fn main() -> Result<(), handle_errors::Error> {
 
     tracing::info!("Q&A service build ID {}", env!("RUST_WEB_DEV_VERSION"));
 
-    run(cfg, store).await;
-
-    Ok(())
+    run(cfg, store).await
 }
@@ -1,6 +1,63 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use std::env;
 
+/// Who may create an account through `/registration`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationMode {
+    /// Anyone can sign up.
+    Open,
+    /// Sign-up needs an invite code issued by an admin.
+    InviteOnly,
+    /// No new accounts.
+    Closed,
+}
+
+/// Which profanity filter censors new content.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfanityFilterKind {
+    /// The APILayer `bad_words` API, needs `BADWORDS_API_KEY`.
+    Api,
+    /// A local wordlist.
+    Wordlist,
+    /// No filtering.
+    None,
+}
+
+/// What happens to a field the profanity filter found bad words in.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfanityPolicy {
+    /// Mask the words with `*`.
+    Censor,
+    /// Refuse the write, listing the words found.
+    Reject,
+    /// Keep the text as written, but hold the post for moderation.
+    Flag,
+}
+
+/// When new posts are checked for profanity.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationMode {
+    /// While the write waits, so it can be refused with the words found.
+    Inline,
+    /// By a background worker. Posts are accepted as pending and published
+    /// once checked.
+    Background,
+}
+
+/// What happens to a write while the profanity filter is unavailable.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfanityFallback {
+    /// Refuse the write.
+    Reject,
+    /// Store the content unfiltered.
+    Accept,
+    /// Censor with the local wordlist instead.
+    Wordlist,
+    /// Store the post as pending moderation, hidden from everyone but its
+    /// author.
+    Pending,
+}
+
 /// Q&A web service API
 #[derive(Parser, Debug, PartialEq)]
 #[clap(author, version, about, long_about = None)]
@@ -26,19 +83,316 @@ pub struct Config {
     /// Database name
     #[clap(long, default_value = "rwd")]
     pub db_name: String,
+    /// PASETO keys as comma separated `name:key` pairs. A 32 byte key issues
+    /// v4.local tokens, a PASERK `k4.secret.` key issues v4.public tokens
+    #[clap(long, value_delimiter = ',')]
+    pub paseto_keys: Vec<String>,
+    /// Name of the key that signs new tokens (defaults to the first key)
+    #[clap(long)]
+    pub paseto_current_key: Option<String>,
+    /// Names of keys that are no longer accepted
+    #[clap(long, value_delimiter = ',')]
+    pub paseto_retired_keys: Vec<String>,
+    /// OpenID Connect issuer URL, enables SSO login when set
+    #[clap(long)]
+    pub oidc_issuer: Option<String>,
+    /// OpenID Connect client id
+    #[clap(long)]
+    pub oidc_client_id: Option<String>,
+    /// OpenID Connect client secret
+    #[clap(long)]
+    pub oidc_client_secret: Option<String>,
+    /// URL of this service's `/oidc/callback` route as registered with the provider
+    #[clap(long)]
+    pub oidc_redirect_url: Option<String>,
+    /// Login requests allowed per client IP and minute
+    #[clap(long, default_value_t = 10u32)]
+    pub login_rate_limit: u32,
+    /// Registration requests allowed per client IP and minute
+    #[clap(long, default_value_t = 5u32)]
+    pub registration_rate_limit: u32,
+    /// Two-factor login requests allowed per client IP and minute
+    #[clap(long, default_value_t = 10u32)]
+    pub two_factor_rate_limit: u32,
+    /// Token refresh requests allowed per client IP and minute
+    #[clap(long, default_value_t = 30u32)]
+    pub refresh_rate_limit: u32,
+    /// SSO callback requests allowed per client IP and minute
+    #[clap(long, default_value_t = 10u32)]
+    pub oidc_rate_limit: u32,
+    /// Write requests allowed per account and minute
+    #[clap(long, default_value_t = 60u32)]
+    pub write_rate_limit: u32,
+    /// Failed logins allowed before an account is locked
+    #[clap(long, default_value_t = 5)]
+    pub lockout_threshold: i32,
+    /// Failed logins allowed from a client IP, over all accounts, before it
+    /// is locked out
+    #[clap(long, default_value_t = 20)]
+    pub lockout_ip_threshold: i32,
+    /// Seconds of the first lockout, doubled with every further failure
+    #[clap(long, default_value_t = 60u64)]
+    pub lockout_seconds: u64,
+    /// Argon2id memory cost in KiB
+    #[clap(long, default_value_t = 19456u32)]
+    pub argon2_memory_kib: u32,
+    /// Argon2id number of passes
+    #[clap(long, default_value_t = 2u32)]
+    pub argon2_iterations: u32,
+    /// Argon2id degree of parallelism
+    #[clap(long, default_value_t = 1u32)]
+    pub argon2_parallelism: u32,
+    /// Who may sign up (open, invite-only, or closed)
+    #[clap(long, value_enum, default_value_t = RegistrationMode::Open)]
+    pub registration_mode: RegistrationMode,
+    /// Profanity filter to use (api, wordlist, or none)
+    #[clap(long, value_enum, default_value_t = ProfanityFilterKind::Api)]
+    pub profanity_filter: ProfanityFilterKind,
+    /// File with one word per line for the wordlist filter (defaults to a
+    /// bundled list)
+    #[clap(long)]
+    pub profanity_wordlist: Option<String>,
+    /// Base URL of the APILayer API
+    #[clap(long, default_value = "https://api.apilayer.com")]
+    pub badwords_url: String,
+    /// APILayer API key
+    #[clap(long)]
+    pub badwords_api_key: Option<String>,
+    /// Milliseconds to wait for a connection to the APILayer API
+    #[clap(long, default_value_t = 2000u64)]
+    pub badwords_connect_timeout_ms: u64,
+    /// Milliseconds a single APILayer request may take
+    #[clap(long, default_value_t = 5000u64)]
+    pub badwords_timeout_ms: u64,
+    /// Retries of a failed APILayer request
+    #[clap(long, default_value_t = 3u32)]
+    pub badwords_max_retries: u32,
+    /// What to do with writes while the profanity filter is unavailable
+    /// (reject, accept, wordlist, or pending)
+    #[clap(long, value_enum, default_value_t = ProfanityFallback::Reject)]
+    pub profanity_fallback: ProfanityFallback,
+    /// Consecutive profanity filter failures that open the circuit breaker
+    #[clap(long, default_value_t = 5u32)]
+    pub profanity_breaker_threshold: u32,
+    /// Seconds the circuit breaker stays open before a trial call
+    #[clap(long, default_value_t = 30u64)]
+    pub profanity_breaker_cooldown_seconds: u64,
+    /// Profanity results kept in memory, 0 disables the cache
+    #[clap(long, default_value_t = 10_000usize)]
+    pub profanity_cache_size: usize,
+    /// Seconds a cached profanity result is reused
+    #[clap(long, default_value_t = 3600u64)]
+    pub profanity_cache_ttl_seconds: u64,
+    /// Profanity policy for question titles (censor, reject, or flag)
+    #[clap(long, value_enum, default_value_t = ProfanityPolicy::Censor)]
+    pub profanity_title_policy: ProfanityPolicy,
+    /// Profanity policy for question content
+    #[clap(long, value_enum, default_value_t = ProfanityPolicy::Censor)]
+    pub profanity_content_policy: ProfanityPolicy,
+    /// Profanity policy for answer content
+    #[clap(long, value_enum, default_value_t = ProfanityPolicy::Censor)]
+    pub profanity_answer_policy: ProfanityPolicy,
+    /// Profanity policy for question tags. Tags are never censored, so
+    /// censor acts as reject
+    #[clap(long, value_enum, default_value_t = ProfanityPolicy::Reject)]
+    pub profanity_tags_policy: ProfanityPolicy,
+    /// When new posts are checked (inline or background)
+    #[clap(long, value_enum, default_value_t = ModerationMode::Background)]
+    pub moderation_mode: ModerationMode,
+    /// Seconds between runs of the moderation worker
+    #[clap(long, default_value_t = 5u64)]
+    pub moderation_interval_seconds: u64,
+    /// Pending posts the moderation worker checks per run
+    #[clap(long, default_value_t = 20i64)]
+    pub moderation_batch_size: i64,
+    /// Posts a profanity re-scan checks per batch
+    #[clap(long, default_value_t = 100i64)]
+    pub profanity_rescan_batch_size: i64,
 }
 
 impl Config {
     pub fn new() -> Result<Config, handle_errors::Error> {
         let config = Config::parse();
 
+        let profanity_filter = match env::var("PROFANITY_FILTER") {
+            Ok(s) => ProfanityFilterKind::from_str(&s, true)
+                .map_err(handle_errors::Error::ConfigError)?,
+            Err(_) => config.profanity_filter,
+        };
+        let profanity_wordlist = env::var("PROFANITY_WORDLIST")
+            .ok()
+            .or(config.profanity_wordlist.to_owned());
+        let badwords_url = env::var("BADWORDS_URL").unwrap_or(config.badwords_url.to_owned());
+        let badwords_api_key = env::var("BADWORDS_API_KEY")
+            .ok()
+            .or(config.badwords_api_key.to_owned());
+        let badwords_connect_timeout_ms = match env::var("BADWORDS_CONNECT_TIMEOUT_MS") {
+            Ok(s) => s.parse::<u64>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.badwords_connect_timeout_ms,
+        };
+        let badwords_timeout_ms = match env::var("BADWORDS_TIMEOUT_MS") {
+            Ok(s) => s.parse::<u64>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.badwords_timeout_ms,
+        };
+        let badwords_max_retries = match env::var("BADWORDS_MAX_RETRIES") {
+            Ok(s) => s.parse::<u32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.badwords_max_retries,
+        };
+        let profanity_fallback = match env::var("PROFANITY_FALLBACK") {
+            Ok(s) => {
+                ProfanityFallback::from_str(&s, true).map_err(handle_errors::Error::ConfigError)?
+            }
+            Err(_) => config.profanity_fallback,
+        };
+        let profanity_breaker_threshold = match env::var("PROFANITY_BREAKER_THRESHOLD") {
+            Ok(s) => s.parse::<u32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.profanity_breaker_threshold,
+        };
+        let profanity_breaker_cooldown_seconds =
+            match env::var("PROFANITY_BREAKER_COOLDOWN_SECONDS") {
+                Ok(s) => s.parse::<u64>().map_err(handle_errors::Error::ParseError)?,
+                Err(_) => config.profanity_breaker_cooldown_seconds,
+            };
+        let profanity_cache_size = match env::var("PROFANITY_CACHE_SIZE") {
+            Ok(s) => s
+                .parse::<usize>()
+                .map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.profanity_cache_size,
+        };
+        let profanity_cache_ttl_seconds = match env::var("PROFANITY_CACHE_TTL_SECONDS") {
+            Ok(s) => s.parse::<u64>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.profanity_cache_ttl_seconds,
+        };
+        let profanity_title_policy = match env::var("PROFANITY_TITLE_POLICY") {
+            Ok(s) => {
+                ProfanityPolicy::from_str(&s, true).map_err(handle_errors::Error::ConfigError)?
+            }
+            Err(_) => config.profanity_title_policy,
+        };
+        let profanity_content_policy = match env::var("PROFANITY_CONTENT_POLICY") {
+            Ok(s) => {
+                ProfanityPolicy::from_str(&s, true).map_err(handle_errors::Error::ConfigError)?
+            }
+            Err(_) => config.profanity_content_policy,
+        };
+        let profanity_answer_policy = match env::var("PROFANITY_ANSWER_POLICY") {
+            Ok(s) => {
+                ProfanityPolicy::from_str(&s, true).map_err(handle_errors::Error::ConfigError)?
+            }
+            Err(_) => config.profanity_answer_policy,
+        };
+        let profanity_tags_policy = match env::var("PROFANITY_TAGS_POLICY") {
+            Ok(s) => {
+                ProfanityPolicy::from_str(&s, true).map_err(handle_errors::Error::ConfigError)?
+            }
+            Err(_) => config.profanity_tags_policy,
+        };
+        let moderation_mode = match env::var("MODERATION_MODE") {
+            Ok(s) => {
+                ModerationMode::from_str(&s, true).map_err(handle_errors::Error::ConfigError)?
+            }
+            Err(_) => config.moderation_mode,
+        };
+        let moderation_interval_seconds = match env::var("MODERATION_INTERVAL_SECONDS") {
+            Ok(s) => s.parse::<u64>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.moderation_interval_seconds,
+        };
+        let moderation_batch_size = match env::var("MODERATION_BATCH_SIZE") {
+            Ok(s) => s.parse::<i64>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.moderation_batch_size,
+        };
+        let profanity_rescan_batch_size = match env::var("PROFANITY_RESCAN_BATCH_SIZE") {
+            Ok(s) => s.parse::<i64>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.profanity_rescan_batch_size,
+        };
+
         // Preflight check that env vars required during runtime are present.
-        if env::var("BADWORDS_API_KEY").is_err() {
-            panic!("BADWORDS_API_KEY not set");
-        }
-        if env::var("PASETO_KEY").is_err() {
-            panic!("PASETO_KEY not set");
-        }
+        // A missing BADWORDS_API_KEY is reported by the api profanity filter.
+        let paseto_keys = match (env::var("PASETO_KEYS"), env::var("PASETO_KEY")) {
+            (Ok(keys), _) => split_list(&keys),
+            // A single key from older deployments keeps working as-is.
+            (Err(_), Ok(key)) => vec![format!("default:{}", key)],
+            (Err(_), Err(_)) if !config.paseto_keys.is_empty() => config.paseto_keys.clone(),
+            (Err(_), Err(_)) => panic!("PASETO_KEYS not set"),
+        };
+        let paseto_current_key = env::var("PASETO_CURRENT_KEY")
+            .ok()
+            .or(config.paseto_current_key.to_owned());
+        let paseto_retired_keys = match env::var("PASETO_RETIRED_KEYS") {
+            Ok(keys) => split_list(&keys),
+            Err(_) => config.paseto_retired_keys.to_owned(),
+        };
+
+        let oidc_issuer = env::var("OIDC_ISSUER")
+            .ok()
+            .or(config.oidc_issuer.to_owned());
+        let oidc_client_id = env::var("OIDC_CLIENT_ID")
+            .ok()
+            .or(config.oidc_client_id.to_owned());
+        let oidc_client_secret = env::var("OIDC_CLIENT_SECRET")
+            .ok()
+            .or(config.oidc_client_secret.to_owned());
+        let oidc_redirect_url = env::var("OIDC_REDIRECT_URL")
+            .ok()
+            .or(config.oidc_redirect_url.to_owned());
+
+        let login_rate_limit = match env::var("LOGIN_RATE_LIMIT") {
+            Ok(s) => s.parse::<u32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.login_rate_limit,
+        };
+        let registration_rate_limit = match env::var("REGISTRATION_RATE_LIMIT") {
+            Ok(s) => s.parse::<u32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.registration_rate_limit,
+        };
+        let two_factor_rate_limit = match env::var("TWO_FACTOR_RATE_LIMIT") {
+            Ok(s) => s.parse::<u32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.two_factor_rate_limit,
+        };
+        let refresh_rate_limit = match env::var("REFRESH_RATE_LIMIT") {
+            Ok(s) => s.parse::<u32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.refresh_rate_limit,
+        };
+        let oidc_rate_limit = match env::var("OIDC_RATE_LIMIT") {
+            Ok(s) => s.parse::<u32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.oidc_rate_limit,
+        };
+        let write_rate_limit = match env::var("WRITE_RATE_LIMIT") {
+            Ok(s) => s.parse::<u32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.write_rate_limit,
+        };
+
+        let lockout_threshold = match env::var("LOCKOUT_THRESHOLD") {
+            Ok(s) => s.parse::<i32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.lockout_threshold,
+        };
+        let lockout_ip_threshold = match env::var("LOCKOUT_IP_THRESHOLD") {
+            Ok(s) => s.parse::<i32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.lockout_ip_threshold,
+        };
+        let lockout_seconds = match env::var("LOCKOUT_SECONDS") {
+            Ok(s) => s.parse::<u64>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.lockout_seconds,
+        };
+
+        let argon2_memory_kib = match env::var("ARGON2_MEMORY_KIB") {
+            Ok(s) => s.parse::<u32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.argon2_memory_kib,
+        };
+        let argon2_iterations = match env::var("ARGON2_ITERATIONS") {
+            Ok(s) => s.parse::<u32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.argon2_iterations,
+        };
+        let argon2_parallelism = match env::var("ARGON2_PARALLELISM") {
+            Ok(s) => s.parse::<u32>().map_err(handle_errors::Error::ParseError)?,
+            Err(_) => config.argon2_parallelism,
+        };
+
+        let registration_mode = match env::var("REGISTRATION_MODE") {
+            Ok(s) => {
+                RegistrationMode::from_str(&s, true).map_err(handle_errors::Error::ConfigError)?
+            }
+            Err(_) => config.registration_mode,
+        };
 
         let port = env::var("PORT")
             .ok()
@@ -63,10 +417,58 @@ impl Config {
             db_host,
             db_port,
             db_name,
+            paseto_keys,
+            paseto_current_key,
+            paseto_retired_keys,
+            oidc_issuer,
+            oidc_client_id,
+            oidc_client_secret,
+            oidc_redirect_url,
+            login_rate_limit,
+            registration_rate_limit,
+            two_factor_rate_limit,
+            refresh_rate_limit,
+            oidc_rate_limit,
+            write_rate_limit,
+            lockout_threshold,
+            lockout_ip_threshold,
+            lockout_seconds,
+            argon2_memory_kib,
+            argon2_iterations,
+            argon2_parallelism,
+            registration_mode,
+            profanity_filter,
+            profanity_wordlist,
+            badwords_url,
+            badwords_api_key,
+            badwords_connect_timeout_ms,
+            badwords_timeout_ms,
+            badwords_max_retries,
+            profanity_fallback,
+            profanity_breaker_threshold,
+            profanity_breaker_cooldown_seconds,
+            profanity_cache_size,
+            profanity_cache_ttl_seconds,
+            profanity_title_policy,
+            profanity_content_policy,
+            profanity_answer_policy,
+            profanity_tags_policy,
+            moderation_mode,
+            moderation_interval_seconds,
+            moderation_batch_size,
+            profanity_rescan_batch_size,
         })
     }
 }
 
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
 #[cfg(test)]
 mod config_tests {
     use super::*;
@@ -82,6 +484,7 @@ mod config_tests {
     }
 
     #[test]
+    #[allow(clippy::redundant_closure)]
     fn unset_and_set_api_key() {
         // ENV VARIABLES ARE NOT SET
         let result = std::panic::catch_unwind(|| Config::new());
@@ -98,10 +501,63 @@ mod config_tests {
             db_host: "localhost".to_string(),
             db_port: 5432,
             db_name: "rwd".to_string(),
+            paseto_keys: vec!["default:RANDOM WORDS WINTER MACINTOSH PC".to_string()],
+            paseto_current_key: None,
+            paseto_retired_keys: vec![],
+            oidc_issuer: None,
+            oidc_client_id: None,
+            oidc_client_secret: None,
+            oidc_redirect_url: None,
+            login_rate_limit: 10,
+            registration_rate_limit: 5,
+            two_factor_rate_limit: 10,
+            refresh_rate_limit: 30,
+            oidc_rate_limit: 10,
+            write_rate_limit: 60,
+            lockout_threshold: 5,
+            lockout_ip_threshold: 20,
+            lockout_seconds: 60,
+            argon2_memory_kib: 19456,
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+            registration_mode: RegistrationMode::Open,
+            profanity_filter: ProfanityFilterKind::Api,
+            profanity_wordlist: None,
+            badwords_url: "https://api.apilayer.com".to_string(),
+            badwords_api_key: Some("API_KEY".to_string()),
+            badwords_connect_timeout_ms: 2000,
+            badwords_timeout_ms: 5000,
+            badwords_max_retries: 3,
+            profanity_fallback: ProfanityFallback::Reject,
+            profanity_breaker_threshold: 5,
+            profanity_breaker_cooldown_seconds: 30,
+            profanity_cache_size: 10_000,
+            profanity_cache_ttl_seconds: 3600,
+            profanity_title_policy: ProfanityPolicy::Censor,
+            profanity_content_policy: ProfanityPolicy::Censor,
+            profanity_answer_policy: ProfanityPolicy::Censor,
+            profanity_tags_policy: ProfanityPolicy::Reject,
+            moderation_mode: ModerationMode::Background,
+            moderation_interval_seconds: 5,
+            moderation_batch_size: 20,
+            profanity_rescan_batch_size: 100,
         };
 
         let config = Config::new().unwrap();
 
         assert_eq!(config, expected);
     }
+
+    #[test]
+    fn registration_modes_parse_from_kebab_case() {
+        assert_eq!(
+            RegistrationMode::from_str("invite-only", true),
+            Ok(RegistrationMode::InviteOnly)
+        );
+        assert_eq!(
+            RegistrationMode::from_str("Closed", true),
+            Ok(RegistrationMode::Closed)
+        );
+        assert!(RegistrationMode::from_str("invite", true).is_err());
+    }
 }
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::fmt::Formatter;
+use std::sync::Arc;
+
+use handle_errors::Error;
+use pasetors::{
+    keys::{AsymmetricPublicKey, AsymmetricSecretKey, SymmetricKey},
+    paserk::{FormatAsPaserk, Id},
+    version4::V4,
+};
+use serde::Serialize;
+
+use crate::config::Config;
+
+/// The set of PASETO keys the service accepts, loaded once at startup.
+///
+/// Keys are configured as `name:secret` pairs. A plain 32 byte secret is a
+/// `v4.local` key, a PASERK `k4.secret.` value is an Ed25519 `v4.public`
+/// key. The type of the current key decides which kind of token is issued.
+///
+/// Tokens carry the PASERK id of the key that produced them in their footer,
+/// so keys can be rotated without logging everyone out: add a new key and
+/// make it current, then retire the old one once its tokens have expired.
+#[derive(Clone)]
+pub struct Keyring {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    current: String,
+    keys: HashMap<String, Key>,
+}
+
+pub enum Key {
+    Local(SymmetricKey<V4>),
+    Public(AsymmetricSecretKey<V4>, AsymmetricPublicKey<V4>),
+}
+
+/// A verification key as published on the well-known endpoint.
+#[derive(Serialize, Debug, Clone)]
+pub struct PublicKey {
+    pub kid: String,
+    pub key: String,
+    pub current: bool,
+}
+
+impl Key {
+    fn parse(name: &str, secret: &str) -> Result<Key, Error> {
+        let invalid = || Error::KeyringError(format!("Invalid key '{}'", name));
+        if secret.starts_with("k4.secret.") {
+            let secret_key = AsymmetricSecretKey::<V4>::try_from(secret).map_err(|_| invalid())?;
+            let public_key =
+                AsymmetricPublicKey::<V4>::try_from(&secret_key).map_err(|_| invalid())?;
+            Ok(Key::Public(secret_key, public_key))
+        } else {
+            let key = SymmetricKey::<V4>::from(secret.as_bytes()).map_err(|_| invalid())?;
+            Ok(Key::Local(key))
+        }
+    }
+
+    fn id(&self) -> String {
+        let id = match self {
+            Key::Local(key) => Id::from(key),
+            Key::Public(_, public_key) => Id::from(public_key),
+        };
+        let mut kid = String::new();
+        id.fmt(&mut kid).expect("Failed to format key id");
+        kid
+    }
+}
+
+impl Keyring {
+    pub fn new(
+        keys: &[String],
+        current: Option<&str>,
+        retired: &[String],
+    ) -> Result<Keyring, Error> {
+        let mut named = Vec::new();
+        for entry in keys {
+            let (name, secret) = entry
+                .split_once(':')
+                .ok_or_else(|| Error::KeyringError(format!("Key entry has no name: {}", entry)))?;
+            named.push((name.to_string(), Key::parse(name, secret)?));
+        }
+
+        let current = match current {
+            Some(current) => current.to_string(),
+            None => match named.first() {
+                Some((name, _)) => name.clone(),
+                None => return Err(Error::KeyringError("No keys configured".to_string())),
+            },
+        };
+        if retired.contains(&current) {
+            return Err(Error::KeyringError(format!(
+                "Current key '{}' is retired",
+                current
+            )));
+        }
+
+        let mut current_kid = None;
+        let mut accepted = HashMap::new();
+        for (name, key) in named {
+            if retired.contains(&name) {
+                continue;
+            }
+            let kid = key.id();
+            if name == current {
+                current_kid = Some(kid.clone());
+            }
+            accepted.insert(kid, key);
+        }
+
+        match current_kid {
+            Some(current) => Ok(Keyring {
+                inner: Arc::new(Inner {
+                    current,
+                    keys: accepted,
+                }),
+            }),
+            None => Err(Error::KeyringError(format!(
+                "Current key '{}' is not configured",
+                current
+            ))),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<Keyring, Error> {
+        Keyring::new(
+            &config.paseto_keys,
+            config.paseto_current_key.as_deref(),
+            &config.paseto_retired_keys,
+        )
+    }
+
+    /// Returns the key id and key used to sign new tokens.
+    pub fn current(&self) -> (&str, &Key) {
+        let kid = &self.inner.current;
+        (kid, &self.inner.keys[kid])
+    }
+
+    /// Looks up a non-retired key by the id found in a token footer.
+    pub fn get(&self, kid: &str) -> Option<&Key> {
+        self.inner.keys.get(kid)
+    }
+
+    /// Lists the public halves of all non-retired `v4.public` keys, so other
+    /// services can verify tokens without sharing a secret.
+    pub fn public_keys(&self) -> Vec<PublicKey> {
+        self.inner
+            .keys
+            .iter()
+            .filter_map(|(kid, key)| match key {
+                Key::Public(_, public_key) => {
+                    let mut paserk = String::new();
+                    public_key
+                        .fmt(&mut paserk)
+                        .expect("Failed to format public key");
+                    Some(PublicKey {
+                        kid: kid.clone(),
+                        key: paserk,
+                        current: *kid == self.inner.current,
+                    })
+                }
+                Key::Local(_) => None,
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Keyring")
+            .field("current", &self.inner.current)
+            .field("keys", &self.inner.keys.keys())
+            .finish()
+    }
+}
@@ -1,3 +1,4 @@
+#![recursion_limit = "256"]
 #![warn(clippy::all)]
 
 pub use handle_errors;
@@ -6,35 +7,93 @@ use tracing_subscriber::fmt::format::FmtSpan;
 use warp::{http::Method, Filter, Reply};
 
 use crate::config::Config;
+use crate::keyring::Keyring;
+use crate::lockout::LockoutPolicy;
+use crate::moderation::{ModerationWorker, RescanJob};
+use crate::oidc::OidcClient;
+use crate::password::PasswordHasher;
+use crate::profanity::ProfanityService;
+use crate::rate_limit::RateLimiter;
 use crate::store::Store;
+use crate::types::moderation::ModerationDecision;
 
 mod account;
 pub mod config;
+mod keyring;
+mod lockout;
+mod moderation;
+mod oidc;
+mod password;
 mod profanity;
+mod rate_limit;
 mod routes;
 mod store;
+mod totp;
 pub mod types;
+mod validation;
 
-async fn build_routes(store: Store) -> impl Filter<Extract = impl Reply> + Clone {
+async fn build_routes(
+    config: &Config,
+    store: Store,
+) -> Result<impl Filter<Extract = impl Reply> + Clone, handle_errors::Error> {
+    let keyring = Keyring::from_config(config)?;
+    let oidc = OidcClient::from_config(config);
+    let policy = LockoutPolicy::from_config(config);
+    let hasher = PasswordHasher::from_config(config);
+    let profanity = ProfanityService::from_config(config)?;
+    store.spawn_revoked_tokens_cleanup();
+    ModerationWorker::from_config(config, store.clone(), &profanity).spawn();
+    let rescans = RescanJob::from_config(config, store.clone(), &profanity);
+    rescans.clone().spawn();
+
+    let auth = routes::authentication::auth(store.clone(), keyring.clone());
+    // Logins and sign-ups are limited per client IP, every authenticated
+    // write per account. Each endpoint has a budget of its own, so e.g.
+    // refreshing tokens doesn't use up the login attempts of a client.
+    let login_limit = rate_limit::by_ip(RateLimiter::per_minute(config.login_rate_limit));
+    let registration_limit =
+        rate_limit::by_ip(RateLimiter::per_minute(config.registration_rate_limit));
+    let two_factor_limit = rate_limit::by_ip(RateLimiter::per_minute(config.two_factor_rate_limit));
+    let refresh_limit = rate_limit::by_ip(RateLimiter::per_minute(config.refresh_rate_limit));
+    let oidc_limit = rate_limit::by_ip(RateLimiter::per_minute(config.oidc_rate_limit));
+    let write_auth = rate_limit::by_account(
+        RateLimiter::per_minute(config.write_rate_limit),
+        auth.clone(),
+    );
     let store_filter = warp::any().map(move || store.clone());
+    let keyring_filter = warp::any().map(move || keyring.clone());
+    let oidc_filter = warp::any().map(move || oidc.clone());
+    let policy_filter = warp::any().map(move || policy);
+    let hasher_filter = warp::any().map(move || hasher);
+    let profanity_filter = warp::any().map(move || profanity.clone());
+    let rescan_filter = warp::any().map(move || rescans.clone());
+    let registration_mode = config.registration_mode;
+    let registration_mode_filter = warp::any().map(move || registration_mode);
 
     let cors = warp::cors()
         .allow_any_origin()
         .allow_header("content-type")
+        .allow_header("x-csrf-token")
         .allow_methods(&[Method::PUT, Method::DELETE, Method::GET, Method::POST]);
 
+    // Reading questions works without a session, which only hides the
+    // private ones.
+    let optional_auth = auth.clone().map(Some).or(warp::any().map(|| None)).unify();
+
     let get_questions = warp::get()
         .and(warp::path("questions"))
         .and(warp::path::end())
         .and(warp::query())
+        .and(optional_auth.clone())
         .and(store_filter.clone())
         .and_then(routes::question::get_questions);
 
     let add_question = warp::post()
         .and(warp::path("questions"))
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(write_auth.clone())
         .and(store_filter.clone())
+        .and(profanity_filter.clone())
         .and(warp::body::json())
         .and_then(routes::question::add_question);
 
@@ -42,8 +101,9 @@ async fn build_routes(store: Store) -> impl Filter<Extract = impl Reply> + Clone
         .and(warp::path("questions"))
         .and(warp::path::param::<i32>())
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(write_auth.clone())
         .and(store_filter.clone())
+        .and(profanity_filter.clone())
         .and(warp::body::json())
         .and_then(routes::question::update_question);
 
@@ -51,42 +111,384 @@ async fn build_routes(store: Store) -> impl Filter<Extract = impl Reply> + Clone
         .and(warp::path("questions"))
         .and(warp::path::param::<i32>())
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(write_auth.clone())
         .and(store_filter.clone())
         .and_then(routes::question::delete_question);
 
     let add_answer = warp::post()
         .and(warp::path("answers"))
         .and(warp::path::end())
-        .and(routes::authentication::auth())
+        .and(write_auth.clone())
         .and(store_filter.clone())
+        .and(profanity_filter.clone())
         .and(warp::body::form())
         .and_then(routes::answer::add_answer);
 
     let registration = warp::post()
         .and(warp::path("registration"))
         .and(warp::path::end())
+        .and(registration_limit)
         .and(store_filter.clone())
+        .and(hasher_filter)
+        .and(registration_mode_filter)
+        .and(profanity_filter.clone())
         .and(warp::body::json())
         .and_then(routes::authentication::register);
 
     let login = warp::post()
         .and(warp::path("login"))
         .and(warp::path::end())
+        .and(login_limit)
         .and(store_filter.clone())
+        .and(keyring_filter.clone())
+        .and(hasher_filter)
+        .and(policy_filter)
+        .and(warp::addr::remote())
         .and(warp::body::json())
         .and_then(routes::authentication::login);
 
-    get_questions
+    let verify_login = warp::post()
+        .and(warp::path("login"))
+        .and(warp::path("2fa"))
+        .and(warp::path::end())
+        .and(two_factor_limit)
+        .and(store_filter.clone())
+        .and(keyring_filter.clone())
+        .and(policy_filter)
+        .and(warp::addr::remote())
+        .and(warp::body::json())
+        .and_then(routes::two_factor::verify_login);
+
+    let refresh = warp::post()
+        .and(warp::path("refresh"))
+        .and(warp::path::end())
+        .and(refresh_limit)
+        .and(store_filter.clone())
+        .and(keyring_filter.clone())
+        .and(routes::authentication::refresh_cookie())
+        .and(warp::body::json())
+        .and_then(routes::authentication::refresh);
+
+    let logout = warp::post()
+        .and(warp::path("logout"))
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and(warp::cookie::optional(
+            routes::authentication::REFRESH_COOKIE,
+        ))
+        .and(warp::body::json())
+        .and_then(routes::authentication::logout);
+
+    let change_password = warp::put()
+        .and(warp::path("me"))
+        .and(warp::path("password"))
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and(hasher_filter)
+        .and(warp::body::json())
+        .and_then(routes::authentication::change_password);
+
+    let login_history = warp::get()
+        .and(warp::path("me"))
+        .and(warp::path("logins"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::authentication::login_history);
+
+    let clear_lockout = warp::delete()
+        .and(warp::path("accounts"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("lockout"))
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::authentication::clear_lockout);
+
+    let add_organization = warp::post()
+        .and(warp::path("organizations"))
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::organization::add_organization);
+
+    let get_organizations = warp::get()
+        .and(warp::path("organizations"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::organization::get_organizations);
+
+    let get_members = warp::get()
+        .and(warp::path("organizations"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("members"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::organization::get_members);
+
+    let add_member = warp::post()
+        .and(warp::path("organizations"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("members"))
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::organization::add_member);
+
+    let update_member = warp::put()
+        .and(warp::path("organizations"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("members"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::organization::update_member);
+
+    let delete_member = warp::delete()
+        .and(warp::path("organizations"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("members"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::organization::delete_member);
+
+    let add_invite = warp::post()
+        .and(warp::path("invites"))
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::invite::add_invite);
+
+    let get_invites = warp::get()
+        .and(warp::path("invites"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::invite::get_invites);
+
+    let delete_invite = warp::delete()
+        .and(warp::path("invites"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::invite::delete_invite);
+
+    let enroll_totp = warp::post()
+        .and(warp::path("me"))
+        .and(warp::path("2fa"))
+        .and(warp::path("totp"))
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::two_factor::enroll_totp);
+
+    let enable_totp = warp::post()
+        .and(warp::path("me"))
+        .and(warp::path("2fa"))
+        .and(warp::path("totp"))
+        .and(warp::path("confirm"))
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::two_factor::enable_totp);
+
+    let disable_totp = warp::delete()
+        .and(warp::path("me"))
+        .and(warp::path("2fa"))
+        .and(warp::path("totp"))
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::two_factor::disable_totp);
+
+    let oidc_login = warp::get()
+        .and(warp::path("oidc"))
+        .and(warp::path("login"))
+        .and(warp::path::end())
+        .and(optional_auth)
+        .and(store_filter.clone())
+        .and(oidc_filter.clone())
+        .and_then(routes::oidc::login);
+
+    let oidc_callback = warp::get()
+        .and(warp::path("oidc"))
+        .and(warp::path("callback"))
+        .and(warp::path::end())
+        .and(oidc_limit)
+        .and(warp::query())
+        .and(warp::cookie::optional::<String>(
+            routes::oidc::OIDC_STATE_COOKIE,
+        ))
+        .and(warp::addr::remote())
+        .and(store_filter.clone())
+        .and(keyring_filter.clone())
+        .and(hasher_filter)
+        .and(registration_mode_filter)
+        .and(profanity_filter.clone())
+        .and(oidc_filter.clone())
+        .and_then(routes::oidc::callback);
+
+    let add_api_key = warp::post()
+        .and(warp::path("me"))
+        .and(warp::path("api-keys"))
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and(warp::body::json())
+        .and_then(routes::api_key::add_api_key);
+
+    let get_api_keys = warp::get()
+        .and(warp::path("me"))
+        .and(warp::path("api-keys"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::api_key::get_api_keys);
+
+    let delete_api_key = warp::delete()
+        .and(warp::path("me"))
+        .and(warp::path("api-keys"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::api_key::delete_api_key);
+
+    let public_keys = warp::get()
+        .and(warp::path(".well-known"))
+        .and(warp::path("paseto-keys"))
+        .and(warp::path::end())
+        .and(keyring_filter.clone())
+        .and_then(routes::authentication::public_keys);
+
+    let get_profanity_audits = warp::get()
+        .and(warp::path("moderation"))
+        .and(warp::path("audits"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::moderation::get_profanity_audits);
+
+    let get_flagged_posts = warp::get()
+        .and(warp::path("moderation"))
+        .and(warp::path("flagged"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::moderation::get_flagged_posts);
+
+    let moderate_question = warp::post()
+        .and(warp::path("moderation"))
+        .and(warp::path("questions"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::param::<ModerationDecision>())
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::moderation::moderate_question);
+
+    let moderate_answer = warp::post()
+        .and(warp::path("moderation"))
+        .and(warp::path("answers"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::param::<ModerationDecision>())
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::moderation::moderate_answer);
+
+    let flush_profanity_cache = warp::delete()
+        .and(warp::path("profanity"))
+        .and(warp::path("cache"))
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and(profanity_filter.clone())
+        .and_then(routes::profanity::flush_cache);
+
+    let start_profanity_rescan = warp::post()
+        .and(warp::path("profanity"))
+        .and(warp::path("rescan"))
+        .and(warp::path::end())
+        .and(write_auth.clone())
+        .and(store_filter.clone())
+        .and(rescan_filter)
+        .and_then(routes::profanity::start_rescan);
+
+    let get_profanity_rescan = warp::get()
+        .and(warp::path("profanity"))
+        .and(warp::path("rescan"))
+        .and(warp::path::end())
+        .and(auth.clone())
+        .and(store_filter.clone())
+        .and_then(routes::profanity::get_rescan);
+
+    let health = warp::get()
+        .and(warp::path("health"))
+        .and(warp::path::end())
+        .and(profanity_filter.clone())
+        .and_then(routes::health::health);
+
+    let routes = get_questions
         .or(add_question)
         .or(add_answer)
         .or(update_question)
         .or(delete_question)
         .or(registration)
         .or(login)
+        .or(verify_login)
+        .or(refresh)
+        .or(logout)
+        .or(change_password)
+        .or(login_history)
+        .or(clear_lockout)
+        .or(add_organization)
+        .or(get_organizations)
+        .or(get_members)
+        .or(add_member)
+        .or(update_member)
+        .or(delete_member)
+        .or(add_invite)
+        .or(get_invites)
+        .or(delete_invite)
+        .or(enroll_totp)
+        .or(enable_totp)
+        .or(disable_totp)
+        .or(oidc_login)
+        .or(oidc_callback)
+        .or(add_api_key)
+        .or(get_api_keys)
+        .or(delete_api_key)
+        .or(public_keys)
+        .or(health)
+        .or(flush_profanity_cache)
+        .or(get_profanity_audits)
+        .or(get_flagged_posts)
+        .or(moderate_question)
+        .or(moderate_answer)
+        .or(start_profanity_rescan)
+        .or(get_profanity_rescan)
         .with(cors)
         .with(warp::trace::request())
-        .recover(handle_errors::return_error)
+        .recover(handle_errors::return_error);
+
+    Ok(routes)
 }
 
 pub async fn setup_store(config: &Config) -> Result<Store, handle_errors::Error> {
@@ -101,6 +503,8 @@ pub async fn setup_store(config: &Config) -> Result<Store, handle_errors::Error>
         .await
         .map_err(handle_errors::Error::MigrationError)?;
 
+    store.load_revoked_tokens().await?;
+
     let log_filter = std::env::var("RUST_LOG").unwrap_or_else(|_| {
         format!(
             "handle_errors={},qna={},warp={}",
@@ -117,17 +521,21 @@ pub async fn setup_store(config: &Config) -> Result<Store, handle_errors::Error>
     Ok(store)
 }
 
-pub async fn run(config: Config, store: Store) {
-    let routes = build_routes(store).await;
+pub async fn run(config: Config, store: Store) -> Result<(), handle_errors::Error> {
+    let routes = build_routes(&config, store).await?;
     warp::serve(routes).run(([0, 0, 0, 0], config.port)).await;
+    Ok(())
 }
 
 pub struct OneshotHandler {
     pub sender: Sender<i32>,
 }
 
-pub async fn oneshot(store: Store) -> OneshotHandler {
-    let routes = build_routes(store).await;
+pub async fn oneshot(
+    config: &Config,
+    store: Store,
+) -> Result<OneshotHandler, handle_errors::Error> {
+    let routes = build_routes(config, store).await?;
     let (tx, rx) = oneshot::channel::<i32>();
     let socket: std::net::SocketAddr = "127.0.0.1:3030"
         .to_string()
@@ -137,5 +545,5 @@ pub async fn oneshot(store: Store) -> OneshotHandler {
         rx.await.ok();
     });
     tokio::task::spawn(server);
-    OneshotHandler { sender: tx }
+    Ok(OneshotHandler { sender: tx })
 }
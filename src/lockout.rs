@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Longest an account can be locked, however many times the password was
+/// guessed wrong.
+const MAX_LOCKOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Decides how long an account is locked after repeated failed logins, and
+/// how long a client IP is locked after failing on any accounts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LockoutPolicy {
+    /// Failed attempts on an account allowed before its first lockout.
+    pub threshold: i32,
+    /// Failed attempts from a client IP allowed before its first lockout.
+    pub ip_threshold: i32,
+    /// Length of the first lockout; each further failure doubles it. The
+    /// failures are forgotten once this long has passed since the last one,
+    /// or since the lockout ended.
+    pub base: Duration,
+}
+
+impl LockoutPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        LockoutPolicy {
+            threshold: config.lockout_threshold,
+            ip_threshold: config.lockout_ip_threshold,
+            base: Duration::from_secs(config.lockout_seconds),
+        }
+    }
+
+    /// Returns how long to lock the account after `failures` consecutive
+    /// failed attempts, if at all.
+    pub fn lock_duration(&self, failures: i32) -> Option<Duration> {
+        self.duration_after(self.threshold, failures)
+    }
+
+    /// Returns how long to lock a client IP after `failures` consecutive
+    /// failed attempts from it, if at all.
+    pub fn ip_lock_duration(&self, failures: i32) -> Option<Duration> {
+        self.duration_after(self.ip_threshold, failures)
+    }
+
+    fn duration_after(&self, threshold: i32, failures: i32) -> Option<Duration> {
+        if failures < threshold {
+            return None;
+        }
+        let exponent = (failures - threshold).min(31) as u32;
+        let duration = self
+            .base
+            .checked_mul(2u32.saturating_pow(exponent))
+            .unwrap_or(MAX_LOCKOUT);
+        Some(duration.min(MAX_LOCKOUT))
+    }
+}
+
+#[cfg(test)]
+mod lockout_tests {
+    use super::{Duration, LockoutPolicy, MAX_LOCKOUT};
+
+    fn policy() -> LockoutPolicy {
+        LockoutPolicy {
+            threshold: 3,
+            ip_threshold: 10,
+            base: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn no_lock_below_threshold() {
+        assert_eq!(policy().lock_duration(2), None);
+    }
+
+    #[test]
+    fn lock_doubles_with_each_failure() {
+        assert_eq!(policy().lock_duration(3), Some(Duration::from_secs(60)));
+        assert_eq!(policy().lock_duration(4), Some(Duration::from_secs(120)));
+        assert_eq!(policy().lock_duration(6), Some(Duration::from_secs(480)));
+    }
+
+    #[test]
+    fn ip_lock_has_its_own_threshold() {
+        assert_eq!(policy().ip_lock_duration(9), None);
+        assert_eq!(policy().ip_lock_duration(10), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn lock_is_capped() {
+        assert_eq!(policy().lock_duration(100), Some(MAX_LOCKOUT));
+    }
+}
@@ -0,0 +1,349 @@
+use std::time::Duration;
+
+use handle_errors::Error;
+use tracing::{event, Level};
+
+use crate::config::Config;
+use crate::profanity::{Field, ProfanityService};
+use crate::store::Store;
+use crate::types::answer::Answer;
+use crate::types::moderation::{ModerationStatus, Rescan, RescanStatus};
+use crate::types::question::Question;
+
+/// How long a worker keeps the pending posts it took. Posts the filter
+/// couldn't check are retried once their claim has expired.
+const CLAIM_TTL: Duration = Duration::from_secs(60);
+
+/// Checks pending posts in the background and publishes, censors or flags
+/// them according to the field policies. Fields that would be rejected
+/// inline are flagged for moderators, as the post was already accepted.
+/// Each instance runs a worker; they claim the posts they check, so no
+/// post is checked twice at the same time.
+#[derive(Debug, Clone)]
+pub struct ModerationWorker {
+    store: Store,
+    profanity: ProfanityService,
+    interval: Duration,
+    batch_size: i64,
+}
+
+impl ModerationWorker {
+    pub fn from_config(config: &Config, store: Store, profanity: &ProfanityService) -> Self {
+        ModerationWorker {
+            store,
+            profanity: profanity.deferred(),
+            interval: Duration::from_secs(config.moderation_interval_seconds.max(1)),
+            batch_size: config.moderation_batch_size.max(1),
+        }
+    }
+
+    pub fn spawn(self) {
+        tokio::spawn(self.run());
+    }
+
+    async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            if let Err(err) = self.moderate_questions().await {
+                event!(Level::ERROR, "Moderating questions failed: {:?}", err);
+            }
+            if let Err(err) = self.moderate_answers().await {
+                event!(Level::ERROR, "Moderating answers failed: {:?}", err);
+            }
+        }
+    }
+
+    /// A post that can't be checked or saved is logged and left for the
+    /// next run, without holding up the rest of the batch.
+    async fn moderate_questions(&self) -> Result<(), Error> {
+        for question in self
+            .store
+            .get_pending_questions(self.batch_size, CLAIM_TTL)
+            .await?
+        {
+            if let Err(err) = self.moderate_question(&question).await {
+                event!(
+                    Level::ERROR,
+                    "Moderating question {} failed: {:?}",
+                    question.id.0,
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn moderate_answers(&self) -> Result<(), Error> {
+        for answer in self
+            .store
+            .get_pending_answers(self.batch_size, CLAIM_TTL)
+            .await?
+        {
+            if let Err(err) = self.moderate_answer(&answer).await {
+                event!(
+                    Level::ERROR,
+                    "Moderating answer {} failed: {:?}",
+                    answer.id.0,
+                    err
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn moderate_question(&self, question: &Question) -> Result<(), Error> {
+        let checked = self
+            .profanity
+            .check_question(
+                question.title.clone(),
+                question.content.clone(),
+                question.tags.clone(),
+            )
+            .await?;
+        if checked.status == ModerationStatus::Pending {
+            return Ok(());
+        }
+        let moderated = Question {
+            id: question.id.clone(),
+            title: checked.title,
+            content: checked.content,
+            tags: checked.tags,
+            organization_id: question.organization_id.clone(),
+            status: checked.status,
+        };
+        if self.store.moderate_question(question, moderated).await? && !checked.audits.is_empty() {
+            self.store
+                .add_profanity_audits(Some(&question.id), None, checked.audits)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn moderate_answer(&self, answer: &Answer) -> Result<(), Error> {
+        let checked = self
+            .profanity
+            .check_fields(vec![(Field::AnswerContent, answer.content.clone())])
+            .await?
+            .remove(0);
+        if checked.status == ModerationStatus::Pending {
+            return Ok(());
+        }
+        let audit = checked.audit(Field::AnswerContent);
+        let moderated = Answer {
+            id: answer.id.clone(),
+            content: checked.content,
+            question_id: answer.question_id.clone(),
+            status: checked.status,
+        };
+        if self.store.moderate_answer(answer, moderated).await? {
+            if let Some(audit) = audit {
+                self.store
+                    .add_profanity_audits(None, Some(&answer.id), vec![audit])
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A running re-scan whose progress wasn't saved for this long is taken
+/// over by another instance, as the one running it stopped.
+const RESCAN_LEASE: Duration = Duration::from_secs(300);
+
+/// Re-runs the active profanity filter over all checked posts, e.g. after
+/// the wordlist or provider changed. Progress is saved after every batch, so
+/// a failed or interrupted re-scan resumes where it stopped. Only one
+/// re-scan runs at a time, on the instance that started or took it over.
+/// Text it censors is kept in the profanity audits.
+#[derive(Debug, Clone)]
+pub struct RescanJob {
+    store: Store,
+    profanity: ProfanityService,
+    batch_size: i64,
+}
+
+impl RescanJob {
+    pub fn from_config(config: &Config, store: Store, profanity: &ProfanityService) -> Self {
+        RescanJob {
+            store,
+            // Posts are only changed by the active filter, never a fallback.
+            profanity: profanity.deferred().without_fallback(),
+            batch_size: config.profanity_rescan_batch_size.max(1),
+        }
+    }
+
+    /// Resumes the last re-scan if it failed, or starts a new one.
+    pub async fn start(&self) -> Result<Rescan, Error> {
+        let rescan = match self.store.get_latest_rescan().await? {
+            Some(rescan) if rescan.status == RescanStatus::Failed => {
+                self.store.restart_rescan(rescan.id).await?
+            }
+            _ => self.store.add_rescan().await?,
+        };
+        let rescan = rescan.ok_or(Error::RescanInProgress)?;
+        // Results cached before the change would hide new matches.
+        self.profanity.flush_cache();
+        tokio::spawn(self.clone().run(rescan.clone()));
+        Ok(rescan)
+    }
+
+    /// Watches for a re-scan whose instance stopped, on every instance, and
+    /// resumes it on the first one to claim it.
+    pub fn spawn(self) {
+        tokio::spawn(self.watch());
+    }
+
+    async fn watch(self) {
+        let mut interval = tokio::time::interval(RESCAN_LEASE / 5);
+        loop {
+            interval.tick().await;
+            if let Err(err) = self.resume_interrupted().await {
+                event!(
+                    Level::ERROR,
+                    "Resuming a profanity re-scan failed: {:?}",
+                    err
+                );
+            }
+        }
+    }
+
+    async fn resume_interrupted(&self) -> Result<(), Error> {
+        let rescan = match self.store.get_latest_rescan().await? {
+            Some(rescan) if rescan.status == RescanStatus::Running => rescan,
+            _ => return Ok(()),
+        };
+        if let Some(rescan) = self.store.claim_rescan(rescan.id, RESCAN_LEASE).await? {
+            self.profanity.flush_cache();
+            tokio::spawn(self.clone().run(rescan));
+        }
+        Ok(())
+    }
+
+    async fn run(self, mut rescan: Rescan) {
+        match self.scan(&mut rescan).await {
+            Ok(()) => rescan.status = RescanStatus::Completed,
+            Err(err) => {
+                event!(
+                    Level::ERROR,
+                    "Profanity re-scan {} failed: {:?}",
+                    rescan.id,
+                    err
+                );
+                rescan.status = RescanStatus::Failed;
+                rescan.error = Some(err.to_string());
+            }
+        }
+        if let Err(err) = self.store.save_rescan(&rescan).await {
+            event!(
+                Level::ERROR,
+                "Saving re-scan {} failed: {:?}",
+                rescan.id,
+                err
+            );
+        }
+    }
+
+    async fn scan(&self, rescan: &mut Rescan) -> Result<(), Error> {
+        loop {
+            let questions = self
+                .store
+                .get_questions_after(rescan.last_question_id, self.batch_size)
+                .await?;
+            if questions.is_empty() {
+                break;
+            }
+            for question in questions {
+                if self.rescan_question(&question).await? {
+                    rescan.questions_changed += 1;
+                }
+                rescan.questions_scanned += 1;
+                rescan.last_question_id = question.id.0;
+            }
+            self.store.save_rescan(rescan).await?;
+        }
+        loop {
+            let answers = self
+                .store
+                .get_answers_after(rescan.last_answer_id, self.batch_size)
+                .await?;
+            if answers.is_empty() {
+                break;
+            }
+            for answer in answers {
+                if self.rescan_answer(&answer).await? {
+                    rescan.answers_changed += 1;
+                }
+                rescan.answers_scanned += 1;
+                rescan.last_answer_id = answer.id.0;
+            }
+            self.store.save_rescan(rescan).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the question was changed. A flagged question stays
+    /// flagged whatever the filter says now.
+    async fn rescan_question(&self, question: &Question) -> Result<bool, Error> {
+        let checked = self
+            .profanity
+            .check_question(
+                question.title.clone(),
+                question.content.clone(),
+                question.tags.clone(),
+            )
+            .await?;
+        let status = question.status.and(checked.status);
+        if checked.title == question.title
+            && checked.content == question.content
+            && checked.tags == question.tags
+            && status == question.status
+        {
+            return Ok(false);
+        }
+        let moderated = Question {
+            id: question.id.clone(),
+            title: checked.title,
+            content: checked.content,
+            tags: checked.tags,
+            organization_id: question.organization_id.clone(),
+            status,
+        };
+        let changed = self.store.moderate_question(question, moderated).await?;
+        if changed && !checked.audits.is_empty() {
+            self.store
+                .add_profanity_audits(Some(&question.id), None, checked.audits)
+                .await?;
+        }
+        Ok(changed)
+    }
+
+    /// Returns whether the answer was changed, like `rescan_question`.
+    async fn rescan_answer(&self, answer: &Answer) -> Result<bool, Error> {
+        let checked = self
+            .profanity
+            .check_fields(vec![(Field::AnswerContent, answer.content.clone())])
+            .await?
+            .remove(0);
+        let status = answer.status.and(checked.status);
+        if checked.content == answer.content && status == answer.status {
+            return Ok(false);
+        }
+        let audit = checked.audit(Field::AnswerContent);
+        let moderated = Answer {
+            id: answer.id.clone(),
+            content: checked.content,
+            question_id: answer.question_id.clone(),
+            status,
+        };
+        let changed = self.store.moderate_answer(answer, moderated).await?;
+        if changed {
+            if let Some(audit) = audit {
+                self.store
+                    .add_profanity_audits(None, Some(&answer.id), vec![audit])
+                    .await?;
+            }
+        }
+        Ok(changed)
+    }
+}
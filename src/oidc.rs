@@ -0,0 +1,295 @@
+use std::sync::Arc;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use handle_errors::Error;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+
+use crate::account::AccountId;
+use crate::config::Config;
+
+/// The parts of the provider's discovery document we use.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct TokenResponse {
+    access_token: String,
+    id_token: String,
+}
+
+/// The claims of the ID token we check ourselves; `iss`, `aud` and `exp`
+/// are checked while decoding it.
+#[derive(Deserialize, Debug, Clone)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+}
+
+/// A login sent to the provider and not back yet.
+#[derive(Debug, Clone)]
+pub struct PendingLogin {
+    pub code_verifier: String,
+    /// Sent with the login and expected back in the ID token.
+    pub nonce: String,
+    /// Account the new identity is linked to, if a signed-in user started
+    /// the login.
+    pub link_account_id: Option<AccountId>,
+}
+
+/// Claims returned by the provider's userinfo endpoint.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UserInfo {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+}
+
+/// Client for an OpenID Connect provider using the authorization-code flow
+/// with PKCE. Provider metadata is discovered on first use, so the service
+/// still starts when the provider is unreachable.
+#[derive(Debug, Clone)]
+pub struct OidcClient {
+    http: reqwest::Client,
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    redirect_url: String,
+    metadata: Arc<OnceCell<ProviderMetadata>>,
+}
+
+impl OidcClient {
+    /// Returns `None` when OIDC login isn't configured.
+    pub fn from_config(config: &Config) -> Option<OidcClient> {
+        match (
+            &config.oidc_issuer,
+            &config.oidc_client_id,
+            &config.oidc_client_secret,
+            &config.oidc_redirect_url,
+        ) {
+            (Some(issuer), Some(client_id), Some(client_secret), Some(redirect_url)) => {
+                Some(OidcClient {
+                    http: reqwest::Client::new(),
+                    issuer: issuer.trim_end_matches('/').to_string(),
+                    client_id: client_id.clone(),
+                    client_secret: client_secret.clone(),
+                    redirect_url: redirect_url.clone(),
+                    metadata: Arc::new(OnceCell::new()),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.issuer
+    }
+
+    async fn metadata(&self) -> Result<&ProviderMetadata, Error> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let metadata = self
+                    .http
+                    .get(format!("{}/.well-known/openid-configuration", self.issuer))
+                    .send()
+                    .await
+                    .map_err(Error::ReqwestAPIError)?
+                    .error_for_status()
+                    .map_err(Error::ReqwestAPIError)?
+                    .json::<ProviderMetadata>()
+                    .await
+                    .map_err(Error::ReqwestAPIError)?;
+                if metadata.issuer.trim_end_matches('/') != self.issuer {
+                    return Err(Error::OidcError("Issuer mismatch".to_string()));
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// Builds the URL the user is sent to for authentication.
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        login: &PendingLogin,
+    ) -> Result<String, Error> {
+        let metadata = self.metadata().await?;
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(login.code_verifier.as_bytes()));
+        let url = reqwest::Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_url),
+                ("scope", "openid email"),
+                ("state", state),
+                ("nonce", &login.nonce),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|_| Error::OidcError("Invalid authorization endpoint".to_string()))?;
+        Ok(url.to_string())
+    }
+
+    /// Exchanges the authorization code, verifies the ID token that comes
+    /// with it, and fetches the claims of the user it names.
+    pub async fn user_info(&self, code: &str, login: &PendingLogin) -> Result<UserInfo, Error> {
+        let metadata = self.metadata().await?;
+        let token = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.redirect_url),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+                ("code_verifier", &login.code_verifier),
+            ])
+            .send()
+            .await
+            .map_err(Error::ReqwestAPIError)?
+            .error_for_status()
+            .map_err(|_| Error::OidcError("Code exchange failed".to_string()))?
+            .json::<TokenResponse>()
+            .await
+            .map_err(Error::ReqwestAPIError)?;
+
+        let claims = self.verify_id_token(&token.id_token, &login.nonce).await?;
+
+        let user = self
+            .http
+            .get(&metadata.userinfo_endpoint)
+            .bearer_auth(token.access_token)
+            .send()
+            .await
+            .map_err(Error::ReqwestAPIError)?
+            .error_for_status()
+            .map_err(|_| Error::OidcError("Userinfo request failed".to_string()))?
+            .json::<UserInfo>()
+            .await
+            .map_err(Error::ReqwestAPIError)?;
+        if user.sub != claims.sub {
+            return Err(Error::OidcError("Userinfo subject mismatch".to_string()));
+        }
+        Ok(user)
+    }
+
+    /// Checks the signature of an ID token against the provider's keys, and
+    /// that it was issued by the provider, to this client, for this login.
+    async fn verify_id_token(&self, id_token: &str, nonce: &str) -> Result<IdTokenClaims, Error> {
+        let metadata = self.metadata().await?;
+        let invalid = |reason: &str| Error::OidcError(format!("Invalid ID token: {}", reason));
+
+        let header = jsonwebtoken::decode_header(id_token).map_err(|_| invalid("malformed"))?;
+        // Shared-secret signatures would make the public keys secrets.
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(invalid("unsupported algorithm"));
+        }
+        // Keys are fetched for every login, so rotated keys work right away.
+        let keys = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .map_err(Error::ReqwestAPIError)?
+            .error_for_status()
+            .map_err(Error::ReqwestAPIError)?
+            .json::<JwkSet>()
+            .await
+            .map_err(Error::ReqwestAPIError)?;
+        let jwk = match &header.kid {
+            Some(kid) => keys.find(kid),
+            None if keys.keys.len() == 1 => keys.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| invalid("unknown signing key"))?;
+        let key = DecodingKey::from_jwk(jwk).map_err(|_| invalid("unusable signing key"))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_required_spec_claims(&["iss", "aud", "exp", "sub"]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|err| invalid(&err.to_string()))?
+            .claims;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(invalid("nonce mismatch"));
+        }
+        Ok(claims)
+    }
+}
+
+#[cfg(test)]
+mod oidc_tests {
+    use std::sync::Arc;
+
+    use mock_server::MockServer;
+    use tokio::sync::OnceCell;
+
+    use super::{OidcClient, PendingLogin};
+
+    fn pending_login(nonce: &str) -> PendingLogin {
+        PendingLogin {
+            code_verifier: "verifier".to_string(),
+            nonce: nonce.to_string(),
+            link_account_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn authorization_code_flow_against_mock_provider() {
+        let socket = "127.0.0.1:3032".parse().expect("Not a valid address");
+        let handler = MockServer::new(socket).oneshot();
+
+        let client = OidcClient {
+            http: reqwest::Client::new(),
+            issuer: "http://127.0.0.1:3032".to_string(),
+            client_id: "qna".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_url: "http://localhost:7878/oidc/callback".to_string(),
+            metadata: Arc::new(OnceCell::new()),
+        };
+
+        let login = pending_login("n0nce");
+        let url = client.authorization_url("state", &login).await.unwrap();
+        assert!(url.starts_with("http://127.0.0.1:3032/authorize?"));
+        assert!(url.contains("code_challenge_method=S256"));
+        assert!(url.contains("nonce=n0nce"));
+        assert!(!url.contains("verifier"));
+
+        // The mock provider puts the nonce of the code into the ID token.
+        let user = client
+            .user_info("mock-authorization-code.n0nce", &login)
+            .await
+            .unwrap();
+        assert_eq!(user.sub, "mock-user-1");
+        assert!(user.email_verified);
+
+        assert!(client
+            .user_info("mock-authorization-code.other", &login)
+            .await
+            .is_err());
+        assert!(client
+            .user_info("mock-authorization-code", &login)
+            .await
+            .is_err());
+        assert!(client.user_info("wrong-code", &login).await.is_err());
+
+        let _ = handler.sender.send(1);
+    }
+}
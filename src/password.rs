@@ -0,0 +1,113 @@
+use std::sync::OnceLock;
+
+use argon2::{Config, Variant, Version};
+use rand::random;
+
+/// Argon2id parameters for password hashes, loaded from `Config`.
+///
+/// Hashes are stored in the encoded PHC format, which records the parameters
+/// they were made with. Logins compare those against the configured ones and
+/// upgrade outdated hashes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PasswordHasher {
+    /// Memory cost in KiB.
+    pub mem_cost: u32,
+    /// Number of passes.
+    pub time_cost: u32,
+    /// Degree of parallelism.
+    pub lanes: u32,
+}
+
+impl PasswordHasher {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        PasswordHasher {
+            mem_cost: config.argon2_memory_kib,
+            time_cost: config.argon2_iterations,
+            lanes: config.argon2_parallelism,
+        }
+    }
+
+    fn argon2_config(&self) -> Config<'static> {
+        Config {
+            variant: Variant::Argon2id,
+            version: Version::Version13,
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            ..Config::default()
+        }
+    }
+
+    pub fn hash(&self, password: &[u8]) -> String {
+        let salt = random::<[u8; 32]>();
+        argon2::hash_encoded(password, &salt, &self.argon2_config()).unwrap()
+    }
+
+    pub fn verify(hash: &str, password: &[u8]) -> Result<bool, argon2::Error> {
+        argon2::verify_encoded(hash, password)
+    }
+
+    /// Whether a stored hash was made with other parameters than the
+    /// configured ones. Hashes that can't be parsed count as outdated.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        // $argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>
+        let parts: Vec<&str> = hash.split('$').collect();
+        if parts.len() != 6 || parts[1] != "argon2id" || parts[2] != "v=19" {
+            return true;
+        }
+        let expected = format!("m={},t={},p={}", self.mem_cost, self.time_cost, self.lanes);
+        parts[3] != expected
+    }
+
+    /// A hash of a random password, used to spend the same time verifying a
+    /// login for an unknown email as for a known one. Made once with the
+    /// parameters of the first caller, which are fixed at startup.
+    pub fn dummy_hash(&self) -> &'static str {
+        static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+        DUMMY_HASH.get_or_init(|| self.hash(&random::<[u8; 32]>()))
+    }
+}
+
+#[cfg(test)]
+mod password_tests {
+    use super::PasswordHasher;
+
+    fn hasher(mem_cost: u32, time_cost: u32) -> PasswordHasher {
+        PasswordHasher {
+            mem_cost,
+            time_cost,
+            lanes: 1,
+        }
+    }
+
+    #[test]
+    fn hash_verifies_with_its_password() {
+        let hash = hasher(1024, 1).hash(b"password");
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert_eq!(PasswordHasher::verify(&hash, b"password"), Ok(true));
+        assert_eq!(PasswordHasher::verify(&hash, b"wrong"), Ok(false));
+    }
+
+    #[test]
+    fn outdated_parameters_need_rehash() {
+        let hash = hasher(1024, 1).hash(b"password");
+        assert!(!hasher(1024, 1).needs_rehash(&hash));
+        assert!(hasher(2048, 1).needs_rehash(&hash));
+        assert!(hasher(1024, 2).needs_rehash(&hash));
+    }
+
+    #[test]
+    fn other_variants_need_rehash() {
+        // Hash made with `argon2::Config::original()`, argon2i.
+        let config = argon2::Config::original();
+        let hash = argon2::hash_encoded(b"password", b"somesaltsomesalt", &config).unwrap();
+        assert!(hasher(4096, 3).needs_rehash(&hash));
+    }
+
+    #[test]
+    fn dummy_hash_rejects_any_password() {
+        let dummy = hasher(1024, 1).dummy_hash();
+        assert_eq!(PasswordHasher::verify(dummy, b"password"), Ok(false));
+        assert_eq!(PasswordHasher::verify(dummy, b""), Ok(false));
+    }
+}
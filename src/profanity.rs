@@ -1,107 +1,774 @@
-use std::env;
-
-use handle_errors::Error;
-use reqwest_middleware::ClientBuilder;
-use reqwest_retry::policies::ExponentialBackoff;
-use reqwest_retry::RetryTransientMiddleware;
-use serde::{Deserialize, Serialize};
-
-#[derive(Deserialize, Serialize, Debug, Clone)]
-pub struct APIResponse {
-    message: String,
-}
-
-#[derive(Deserialize, Serialize, Debug, Clone)]
-struct BadWord {
-    original: String,
-    word: String,
-    deviations: i64,
-    info: i64,
-    #[serde(rename = "replacedLen")]
-    replace_len: i64,
-}
-
-#[derive(Deserialize, Serialize, Debug, Clone)]
-struct BadWordResponse {
-    content: String,
-    bad_words_total: i64,
-    bad_words_list: Vec<BadWord>,
-    censored_content: String,
-}
-
-pub async fn check_profanity(content: String) -> Result<String, Error> {
-    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
-    let client = ClientBuilder::new(reqwest::Client::new())
-        .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-        .build();
-    let api_key = env::var("BADWORDS_API_KEY").expect("Env var BADWORDS_API_KEY must be set");
-    let api_layer_url = env::var("BADWORDS_URL").expect("Env var BADWORDS_URL must be set");
-    let res = client
-        .post(format!("{}/bad_words?censor_character=*", api_layer_url))
-        .header("apikey", api_key)
-        .body(content)
-        .send()
-        .await
-        .map_err(Error::MiddlewareReqwestAPIError)?;
-
-    if !res.status().is_success() {
-        return if res.status().is_client_error() {
-            let err = transform_error(res).await;
-            Err(Error::ClientError(err))
-        } else {
-            let err = transform_error(res).await;
-            Err(Error::ServerError(err))
-        };
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures_util::future::try_join_all;
+use handle_errors::{Error, FieldError};
+use serde::Serialize;
+
+use crate::config::{
+    Config, ModerationMode, ProfanityFallback, ProfanityFilterKind, ProfanityPolicy,
+};
+use crate::types::moderation::{ModerationStatus, NewProfanityAudit};
+
+mod api_layer;
+mod breaker;
+mod cache;
+mod wordlist;
+
+pub use api_layer::ApiLayerFilter;
+pub use breaker::{BreakerStatus, CircuitBreaker};
+pub use cache::{CacheStats, ProfanityCache};
+pub use wordlist::WordlistFilter;
+
+/// A text with its profane words masked.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Censored {
+    pub content: String,
+    /// The masked words as they were written.
+    pub bad_words: Vec<String>,
+}
+
+impl Censored {
+    /// A text without bad words.
+    pub fn unchanged(content: String) -> Self {
+        Censored {
+            content,
+            bad_words: Vec::new(),
+        }
     }
+}
+
+/// Censors profane words in user content before it is stored.
+#[async_trait]
+pub trait ProfanityFilter: Send + Sync + Debug {
+    /// Returns the content with every profane word masked.
+    async fn censor(&self, content: String) -> Result<Censored, Error>;
+
+    /// Censors several texts, returned in the same order. Filters whose
+    /// provider takes batches should override this; by default the texts
+    /// are censored concurrently.
+    async fn censor_batch(&self, contents: Vec<String>) -> Result<Vec<Censored>, Error> {
+        try_join_all(contents.into_iter().map(|content| self.censor(content))).await
+    }
+}
+
+/// Filter shared between all requests.
+pub type SharedFilter = Arc<dyn ProfanityFilter>;
+
+/// Leaves content as it is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopFilter;
+
+#[async_trait]
+impl ProfanityFilter for NoopFilter {
+    async fn censor(&self, content: String) -> Result<Censored, Error> {
+        Ok(Censored::unchanged(content))
+    }
+}
+
+/// Builds the filter selected in the configuration.
+pub fn filter_from_config(config: &Config) -> Result<SharedFilter, Error> {
+    Ok(match config.profanity_filter {
+        ProfanityFilterKind::Api => Arc::new(ApiLayerFilter::from_config(config)?),
+        ProfanityFilterKind::Wordlist => Arc::new(WordlistFilter::from_config(config)?),
+        ProfanityFilterKind::None => Arc::new(NoopFilter),
+    })
+}
+
+/// Content after the profanity check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checked {
+    pub content: String,
+    /// Pending content went unchecked or was flagged, and has to wait for
+    /// moderation.
+    pub status: ModerationStatus,
+    pub bad_words: Vec<String>,
+    /// The text as it was written.
+    pub original: String,
+}
+
+impl Checked {
+    /// The audit record of a field the filter changed or flagged.
+    pub fn audit(&self, field: Field) -> Option<NewProfanityAudit> {
+        if self.content == self.original && self.bad_words.is_empty() {
+            return None;
+        }
+        Some(NewProfanityAudit {
+            field: field.name().to_string(),
+            original: self.original.clone(),
+            censored: self.content.clone(),
+            bad_words: self.bad_words.clone(),
+        })
+    }
+}
+
+/// A question's user-supplied fields after the profanity check.
+#[derive(Debug, Clone)]
+pub struct CheckedQuestion {
+    pub title: String,
+    pub content: String,
+    pub tags: Option<Vec<String>>,
+    pub status: ModerationStatus,
+    /// Fields the filter changed.
+    pub audits: Vec<NewProfanityAudit>,
+}
+
+/// User-supplied fields with a profanity policy of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    QuestionTitle,
+    QuestionContent,
+    AnswerContent,
+    Tag,
+    AccountEmail,
+}
 
-    match res.json::<BadWordResponse>().await {
-        Ok(res) => Ok(res.censored_content),
-        Err(err) => Err(Error::ReqwestAPIError(err)),
+impl Field {
+    /// Name of the field in request bodies and validation errors.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::QuestionTitle => "title",
+            Field::QuestionContent | Field::AnswerContent => "content",
+            Field::Tag => "tags",
+            Field::AccountEmail => "email",
+        }
     }
 }
 
-async fn transform_error(res: reqwest::Response) -> handle_errors::APILayerError {
-    handle_errors::APILayerError {
-        status: res.status().as_u16(),
-        message: res.json::<APIResponse>().await.unwrap().message,
+/// The configured policy of each field.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldPolicies {
+    pub question_title: ProfanityPolicy,
+    pub question_content: ProfanityPolicy,
+    pub answer_content: ProfanityPolicy,
+    pub tags: ProfanityPolicy,
+}
+
+impl FieldPolicies {
+    pub fn from_config(config: &Config) -> Self {
+        FieldPolicies {
+            question_title: config.profanity_title_policy,
+            question_content: config.profanity_content_policy,
+            answer_content: config.profanity_answer_policy,
+            tags: config.profanity_tags_policy,
+        }
+    }
+
+    /// Tags and account fields are never censored, as asterisks would make
+    /// them useless: censor is taken as reject for tags, and account fields
+    /// are always rejected.
+    pub fn get(&self, field: Field) -> ProfanityPolicy {
+        match field {
+            Field::QuestionTitle => self.question_title,
+            Field::QuestionContent => self.question_content,
+            Field::AnswerContent => self.answer_content,
+            Field::Tag => match self.tags {
+                ProfanityPolicy::Censor => ProfanityPolicy::Reject,
+                policy => policy,
+            },
+            Field::AccountEmail => ProfanityPolicy::Reject,
+        }
+    }
+}
+
+impl Default for FieldPolicies {
+    fn default() -> Self {
+        FieldPolicies {
+            question_title: ProfanityPolicy::Censor,
+            question_content: ProfanityPolicy::Censor,
+            answer_content: ProfanityPolicy::Censor,
+            tags: ProfanityPolicy::Reject,
+        }
+    }
+}
+
+/// Profanity filter and fallback state as shown on the health endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfanityHealth {
+    pub fallback: &'static str,
+    pub breaker: BreakerStatus,
+    pub cache: CacheStats,
+}
+
+/// Runs the configured filter behind a cache and a circuit breaker, and
+/// falls back as configured while it is unavailable.
+#[derive(Debug, Clone)]
+pub struct ProfanityService {
+    filter: SharedFilter,
+    cache: Arc<ProfanityCache>,
+    breaker: Arc<CircuitBreaker>,
+    fallback: ProfanityFallback,
+    /// The local list, for email addresses and the wordlist fallback.
+    wordlist: Arc<WordlistFilter>,
+    policies: FieldPolicies,
+    /// Checks of posts that were already accepted flag the fields they
+    /// would reject.
+    deferred: bool,
+    mode: ModerationMode,
+}
+
+impl ProfanityService {
+    pub fn new(
+        filter: SharedFilter,
+        cache: ProfanityCache,
+        breaker: CircuitBreaker,
+        fallback: ProfanityFallback,
+        wordlist: WordlistFilter,
+    ) -> Self {
+        ProfanityService {
+            filter,
+            cache: Arc::new(cache),
+            breaker: Arc::new(breaker),
+            fallback,
+            wordlist: Arc::new(wordlist),
+            policies: FieldPolicies::default(),
+            deferred: false,
+            mode: ModerationMode::Inline,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: ModerationMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_policies(mut self, policies: FieldPolicies) -> Self {
+        self.policies = policies;
+        self
+    }
+
+    /// The same service for checking posts after they were accepted.
+    pub fn deferred(&self) -> Self {
+        ProfanityService {
+            deferred: true,
+            ..self.clone()
+        }
+    }
+
+    /// The same service without the fallback: texts the filter couldn't
+    /// check fail with `ProfanityFilterUnavailable` instead.
+    pub fn without_fallback(&self) -> Self {
+        ProfanityService {
+            fallback: ProfanityFallback::Reject,
+            ..self.clone()
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self, Error> {
+        Ok(ProfanityService::new(
+            filter_from_config(config)?,
+            ProfanityCache::new(
+                config.profanity_cache_size,
+                Duration::from_secs(config.profanity_cache_ttl_seconds),
+            ),
+            CircuitBreaker::new(
+                config.profanity_breaker_threshold,
+                Duration::from_secs(config.profanity_breaker_cooldown_seconds),
+            ),
+            config.profanity_fallback,
+            WordlistFilter::from_config(config)?,
+        )
+        .with_policies(FieldPolicies::from_config(config))
+        .with_mode(config.moderation_mode))
+    }
+
+    /// Checks the fields of a post and applies each field's policy. The
+    /// results are in the order of the fields. Fields with the reject policy
+    /// fail the whole check with a validation error listing the words found.
+    pub async fn check_fields(&self, fields: Vec<(Field, String)>) -> Result<Vec<Checked>, Error> {
+        let (names, contents): (Vec<Field>, Vec<String>) = fields.into_iter().unzip();
+        let checked = self.check_many(contents).await?;
+
+        let mut errors = Vec::new();
+        let checked = names
+            .into_iter()
+            .zip(checked)
+            .map(|(field, checked)| {
+                if checked.bad_words.is_empty() {
+                    return checked;
+                }
+                let policy = match self.policies.get(field) {
+                    ProfanityPolicy::Reject if self.deferred => ProfanityPolicy::Flag,
+                    policy => policy,
+                };
+                match policy {
+                    ProfanityPolicy::Censor => checked,
+                    ProfanityPolicy::Reject => {
+                        let words = checked.bad_words.join(", ");
+                        let message = match field {
+                            Field::Tag => {
+                                format!(
+                                    "tag '{}' contains disallowed words: {}",
+                                    checked.original, words
+                                )
+                            }
+                            _ => format!("contains disallowed words: {}", words),
+                        };
+                        errors.push(FieldError {
+                            field: field.name().to_string(),
+                            message,
+                        });
+                        checked
+                    }
+                    ProfanityPolicy::Flag => Checked {
+                        content: checked.original.clone(),
+                        status: ModerationStatus::Flagged,
+                        ..checked
+                    },
+                }
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(Error::ValidationError(errors));
+        }
+        Ok(checked)
+    }
+
+    /// Checks a new or edited question, or leaves it pending for the
+    /// moderation worker in background mode.
+    pub async fn submit_question(
+        &self,
+        title: String,
+        content: String,
+        tags: Option<Vec<String>>,
+    ) -> Result<CheckedQuestion, Error> {
+        match self.mode {
+            ModerationMode::Inline => self.check_question(title, content, tags).await,
+            ModerationMode::Background => Ok(CheckedQuestion {
+                title,
+                content,
+                tags,
+                status: ModerationStatus::Pending,
+                audits: Vec::new(),
+            }),
+        }
+    }
+
+    /// Checks a new answer, or leaves it pending like `submit_question`.
+    pub async fn submit_answer(&self, content: String) -> Result<Checked, Error> {
+        match self.mode {
+            ModerationMode::Inline => Ok(self
+                .check_fields(vec![(Field::AnswerContent, content)])
+                .await?
+                .remove(0)),
+            ModerationMode::Background => Ok(Checked {
+                content: content.clone(),
+                status: ModerationStatus::Pending,
+                bad_words: Vec::new(),
+                original: content,
+            }),
+        }
+    }
+
+    /// Checks the title, content and tags of a question in one go.
+    pub async fn check_question(
+        &self,
+        title: String,
+        content: String,
+        tags: Option<Vec<String>>,
+    ) -> Result<CheckedQuestion, Error> {
+        let has_tags = tags.is_some();
+        let mut fields = vec![
+            (Field::QuestionTitle, title),
+            (Field::QuestionContent, content),
+        ];
+        fields.extend(tags.into_iter().flatten().map(|tag| (Field::Tag, tag)));
+
+        let mut checked = self.check_fields(fields).await?.into_iter();
+        let (title, content) = match (checked.next(), checked.next()) {
+            (Some(title), Some(content)) => (title, content),
+            _ => unreachable!("one result per field"),
+        };
+        let mut status = title.status.and(content.status);
+        let mut audits: Vec<NewProfanityAudit> = title
+            .audit(Field::QuestionTitle)
+            .into_iter()
+            .chain(content.audit(Field::QuestionContent))
+            .collect();
+        let mut tags = Vec::new();
+        for tag in checked {
+            status = status.and(tag.status);
+            audits.extend(tag.audit(Field::Tag));
+            tags.push(tag.content);
+        }
+        Ok(CheckedQuestion {
+            title: title.content,
+            content: content.content,
+            tags: has_tags.then_some(tags),
+            status,
+            audits,
+        })
+    }
+
+    /// Checks an email address for a new account against the local
+    /// wordlist only, so addresses are never sent to the filter provider.
+    /// Its separators are turned into spaces first, so the words of
+    /// `bad.word@example.com` are found on their own.
+    pub fn check_email(&self, email: &str) -> Result<(), Error> {
+        let words = email.replace(['@', '.', '+', '_', '-'], " ");
+        let censored = self.wordlist.censor_text(&words);
+        if censored.bad_words.is_empty() {
+            return Ok(());
+        }
+        Err(Error::ValidationError(vec![FieldError {
+            field: Field::AccountEmail.name().to_string(),
+            message: format!(
+                "contains disallowed words: {}",
+                censored.bad_words.join(", ")
+            ),
+        }]))
+    }
+
+    /// Censors several texts with one call to the filter, falling back for
+    /// those it couldn't check. The results are in the order of the texts.
+    async fn check_many(&self, contents: Vec<String>) -> Result<Vec<Checked>, Error> {
+        let mut censored: Vec<Option<Censored>> = contents
+            .iter()
+            .map(|content| self.cache.get(content))
+            .collect();
+        let missing: Vec<usize> = (0..contents.len())
+            .filter(|&i| censored[i].is_none())
+            .collect();
+
+        if !missing.is_empty() && self.breaker.allow() {
+            let batch = missing.iter().map(|&i| contents[i].clone()).collect();
+            match self.filter.censor_batch(batch).await {
+                Ok(results) if results.len() == missing.len() => {
+                    self.breaker.record_success();
+                    for (&i, result) in missing.iter().zip(results) {
+                        self.cache.insert(&contents[i], result.clone());
+                        censored[i] = Some(result);
+                    }
+                }
+                Ok(results) => {
+                    tracing::event!(
+                        tracing::Level::ERROR,
+                        "Profanity filter returned {} results for {} texts",
+                        results.len(),
+                        missing.len()
+                    );
+                    self.breaker.record_failure();
+                }
+                Err(err) => {
+                    tracing::event!(tracing::Level::ERROR, "Profanity filter failed: {:?}", err);
+                    self.breaker.record_failure();
+                }
+            }
+        }
+
+        contents
+            .into_iter()
+            .zip(censored)
+            .map(|(original, censored)| match censored {
+                Some(censored) => Ok(Checked {
+                    content: censored.content,
+                    status: ModerationStatus::Approved,
+                    bad_words: censored.bad_words,
+                    original,
+                }),
+                None => self.fall_back(original),
+            })
+            .collect()
+    }
+
+    fn fall_back(&self, original: String) -> Result<Checked, Error> {
+        let (censored, status) = match self.fallback {
+            ProfanityFallback::Accept => (
+                Censored::unchanged(original.clone()),
+                ModerationStatus::Approved,
+            ),
+            ProfanityFallback::Wordlist => (
+                self.wordlist.censor_text(&original),
+                ModerationStatus::Approved,
+            ),
+            ProfanityFallback::Pending => (
+                Censored::unchanged(original.clone()),
+                ModerationStatus::Pending,
+            ),
+            ProfanityFallback::Reject => return Err(Error::ProfanityFilterUnavailable),
+        };
+        Ok(Checked {
+            content: censored.content,
+            status,
+            bad_words: censored.bad_words,
+            original,
+        })
+    }
+
+    pub fn health(&self) -> ProfanityHealth {
+        ProfanityHealth {
+            fallback: match self.fallback {
+                ProfanityFallback::Reject => "reject",
+                ProfanityFallback::Accept => "accept",
+                ProfanityFallback::Wordlist => "wordlist",
+                ProfanityFallback::Pending => "pending",
+            },
+            breaker: self.breaker.status(),
+            cache: self.cache.stats(),
+        }
+    }
+
+    /// Drops all cached results, e.g. after the wordlist changed. Returns
+    /// the number of entries dropped.
+    pub fn flush_cache(&self) -> usize {
+        self.cache.flush()
     }
 }
+
 #[cfg(test)]
 mod profanity_tests {
-    use mock_server::{MockServer, OneshotHandler};
+    use super::*;
 
-    use super::{check_profanity, env};
+    /// Fails every call, like an unreachable API.
+    #[derive(Debug)]
+    struct DownFilter;
 
-    #[tokio::test]
-    async fn run() {
-        let handler = run_mock();
-        censor_profane_words().await;
-        no_profane_words().await;
-        let _ = handler.sender.send(1);
+    #[async_trait]
+    impl ProfanityFilter for DownFilter {
+        async fn censor(&self, _content: String) -> Result<Censored, Error> {
+            Err(Error::ProfanityFilterUnavailable)
+        }
     }
 
-    fn run_mock() -> OneshotHandler {
-        env::set_var("BADWORDS_URL", "http://127.0.0.1:3030");
-        env::set_var("BADWORDS_API_KEY", "YES");
+    fn service(fallback: ProfanityFallback) -> ProfanityService {
+        ProfanityService::new(
+            Arc::new(DownFilter),
+            ProfanityCache::new(10, Duration::from_secs(60)),
+            CircuitBreaker::new(2, Duration::from_secs(60)),
+            fallback,
+            WordlistFilter::new("shitty"),
+        )
+    }
 
-        let socket = "127.0.0.1:3030"
-            .to_string()
-            .parse()
-            .expect("Not a valid address");
-        let mock = MockServer::new(socket);
+    fn wordlist_service(policies: FieldPolicies) -> ProfanityService {
+        ProfanityService::new(
+            Arc::new(WordlistFilter::new("shitty")),
+            ProfanityCache::new(10, Duration::from_secs(60)),
+            CircuitBreaker::new(2, Duration::from_secs(60)),
+            ProfanityFallback::Reject,
+            WordlistFilter::new("shitty"),
+        )
+        .with_policies(policies)
+    }
 
-        mock.oneshot()
+    async fn check(service: &ProfanityService, content: String) -> Result<Checked, Error> {
+        let mut checked = service
+            .check_fields(vec![(Field::AnswerContent, content)])
+            .await?;
+        Ok(checked.remove(0))
     }
 
-    async fn censor_profane_words() {
+    #[tokio::test]
+    async fn noop_keeps_content() {
+        let content = "This is a shitty sentence".to_string();
+        assert_eq!(
+            NoopFilter.censor(content.clone()).await.unwrap(),
+            Censored::unchanged(content)
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_while_unavailable() {
         let content = "This is a shitty sentence".to_string();
-        let censored_content = check_profanity(content).await;
-        assert_eq!(censored_content.unwrap(), "this is a ****** sentence");
+        assert!(check(&service(ProfanityFallback::Reject), content.clone())
+            .await
+            .is_err());
+        assert_eq!(
+            check(&service(ProfanityFallback::Accept), content.clone())
+                .await
+                .unwrap(),
+            Checked {
+                content: content.clone(),
+                status: ModerationStatus::Approved,
+                bad_words: vec![],
+                original: content.clone(),
+            }
+        );
+        assert_eq!(
+            check(&service(ProfanityFallback::Wordlist), content.clone())
+                .await
+                .unwrap()
+                .content,
+            "This is a ****** sentence"
+        );
+        assert_eq!(
+            check(&service(ProfanityFallback::Pending), content)
+                .await
+                .unwrap()
+                .status,
+            ModerationStatus::Pending
+        );
     }
 
-    async fn no_profane_words() {
-        let content = "this is a sentence".to_string();
-        let censored_content = check_profanity(content).await;
-        assert_eq!(censored_content.unwrap(), "");
+    #[tokio::test]
+    async fn fails_without_fallback_while_unavailable() {
+        for fallback in [
+            ProfanityFallback::Accept,
+            ProfanityFallback::Wordlist,
+            ProfanityFallback::Pending,
+        ] {
+            let service = service(fallback).without_fallback();
+            assert!(matches!(
+                check(&service, "clean".to_string()).await,
+                Err(Error::ProfanityFilterUnavailable)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn failures_open_the_breaker() {
+        let service = service(ProfanityFallback::Accept);
+        for _ in 0..2 {
+            check(&service, "text".to_string()).await.unwrap();
+        }
+        assert_eq!(service.health().breaker.state, "open");
+    }
+
+    #[tokio::test]
+    async fn reuses_cached_results() {
+        let service = wordlist_service(FieldPolicies::default());
+        for _ in 0..2 {
+            let checked = check(&service, "shitty".to_string()).await.unwrap();
+            assert_eq!(checked.content, "******");
+        }
+        let health = service.health();
+        assert_eq!((health.cache.hits, health.cache.misses), (1, 1));
+        assert_eq!(service.flush_cache(), 1);
+    }
+
+    #[tokio::test]
+    async fn checks_fields_in_order() {
+        let service = wordlist_service(FieldPolicies::default());
+        check(&service, "shitty title".to_string()).await.unwrap();
+        let checked = service
+            .check_fields(vec![
+                (Field::QuestionTitle, "shitty title".to_string()),
+                (Field::QuestionContent, "clean content".to_string()),
+                (Field::QuestionContent, "shitty content".to_string()),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(checked[1].audit(Field::QuestionContent), None);
+        assert_eq!(
+            checked[2].audit(Field::QuestionContent),
+            Some(NewProfanityAudit {
+                field: "content".to_string(),
+                original: "shitty content".to_string(),
+                censored: "****** content".to_string(),
+                bad_words: vec!["shitty".to_string()],
+            })
+        );
+        let contents: Vec<_> = checked.into_iter().map(|c| c.content).collect();
+        assert_eq!(
+            contents,
+            ["****** title", "clean content", "****** content"]
+        );
+    }
+
+    #[tokio::test]
+    async fn applies_the_policy_of_each_field() {
+        let service = wordlist_service(FieldPolicies {
+            question_title: ProfanityPolicy::Reject,
+            question_content: ProfanityPolicy::Flag,
+            ..FieldPolicies::default()
+        });
+
+        match service
+            .check_fields(vec![
+                (Field::QuestionTitle, "a shitty title".to_string()),
+                (Field::QuestionContent, "clean content".to_string()),
+            ])
+            .await
+        {
+            Err(Error::ValidationError(errors)) => assert_eq!(
+                errors,
+                [FieldError {
+                    field: "title".to_string(),
+                    message: "contains disallowed words: shitty".to_string(),
+                }]
+            ),
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+
+        let checked = service
+            .check_fields(vec![(Field::QuestionContent, "shitty content".to_string())])
+            .await
+            .unwrap();
+        assert_eq!(checked[0].content, "shitty content");
+        assert_eq!(checked[0].status, ModerationStatus::Flagged);
+    }
+
+    #[tokio::test]
+    async fn never_censors_tags_or_emails() {
+        let service = wordlist_service(FieldPolicies {
+            tags: ProfanityPolicy::Censor,
+            ..FieldPolicies::default()
+        });
+
+        match service
+            .check_fields(vec![(Field::Tag, "shitty".to_string())])
+            .await
+        {
+            Err(Error::ValidationError(errors)) => assert_eq!(
+                errors,
+                [FieldError {
+                    field: "tags".to_string(),
+                    message: "tag 'shitty' contains disallowed words: shitty".to_string(),
+                }]
+            ),
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+
+        assert!(service.check_email("jane.doe@example.com").is_ok());
+        match service.check_email("shitty.name@example.com") {
+            Err(Error::ValidationError(errors)) => assert_eq!(errors[0].field, "email"),
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+
+        // Emails never reach the provider, nor its fallback.
+        let unavailable = self::service(ProfanityFallback::Pending);
+        assert!(unavailable.check_email("jane.doe@example.com").is_ok());
+        assert!(matches!(
+            unavailable.check_email("shitty.name@example.com"),
+            Err(Error::ValidationError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn deferred_checks_flag_instead_of_rejecting() {
+        let service = wordlist_service(FieldPolicies::default()).deferred();
+        let checked = service
+            .check_question(
+                "clean title".to_string(),
+                "shitty content".to_string(),
+                Some(vec!["shitty".to_string()]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(checked.content, "****** content");
+        assert_eq!(checked.tags, Some(vec!["shitty".to_string()]));
+        assert_eq!(checked.status, ModerationStatus::Flagged);
+        assert_eq!(checked.audits.len(), 2);
+        assert_eq!(checked.audits[1].field, "tags");
+        assert_eq!(checked.audits[1].bad_words, vec!["shitty".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn background_mode_leaves_posts_pending() {
+        let service =
+            wordlist_service(FieldPolicies::default()).with_mode(ModerationMode::Background);
+        let checked = service
+            .submit_question("shitty title".to_string(), "content".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(checked.title, "shitty title");
+        assert_eq!(checked.status, ModerationStatus::Pending);
+        assert!(checked.audits.is_empty());
+
+        let checked = service.submit_answer("shitty".to_string()).await.unwrap();
+        assert_eq!(checked.status, ModerationStatus::Pending);
+        assert_eq!(checked.audit(Field::AnswerContent), None);
     }
 }
@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use handle_errors::Error;
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+use reqwest_retry::policies::ExponentialBackoff;
+use reqwest_retry::RetryTransientMiddleware;
+use serde::{Deserialize, Serialize};
+
+use super::{Censored, ProfanityFilter};
+use crate::config::Config;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct APIResponse {
+    message: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct BadWord {
+    original: String,
+    word: String,
+    deviations: i64,
+    info: i64,
+    #[serde(rename = "replacedLen")]
+    replace_len: i64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct BadWordResponse {
+    content: String,
+    bad_words_total: i64,
+    bad_words_list: Vec<BadWord>,
+    censored_content: String,
+}
+
+/// Timeouts and retries of the HTTP client talking to the API.
+#[derive(Debug, Clone, Copy)]
+pub struct HttpOptions {
+    pub connect_timeout: Duration,
+    /// Limit for a single attempt, retries get their own.
+    pub timeout: Duration,
+    pub max_retries: u32,
+}
+
+impl HttpOptions {
+    pub fn from_config(config: &Config) -> Self {
+        HttpOptions {
+            connect_timeout: Duration::from_millis(config.badwords_connect_timeout_ms),
+            timeout: Duration::from_millis(config.badwords_timeout_ms),
+            max_retries: config.badwords_max_retries,
+        }
+    }
+}
+
+/// Client for the APILayer `bad_words` API. The HTTP client is built once,
+/// so connections are pooled across requests.
+#[derive(Debug, Clone)]
+pub struct ApiLayerFilter {
+    client: ClientWithMiddleware,
+    url: String,
+    api_key: String,
+}
+
+impl ApiLayerFilter {
+    pub fn new(url: &str, api_key: &str, options: HttpOptions) -> Result<ApiLayerFilter, Error> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(options.connect_timeout)
+            .timeout(options.timeout)
+            .build()
+            .map_err(Error::ReqwestAPIError)?;
+        let retry_policy =
+            ExponentialBackoff::builder().build_with_max_retries(options.max_retries);
+        let client = ClientBuilder::new(http)
+            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+            .build();
+        Ok(ApiLayerFilter {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        })
+    }
+
+    pub fn from_config(config: &Config) -> Result<ApiLayerFilter, Error> {
+        match &config.badwords_api_key {
+            Some(api_key) => ApiLayerFilter::new(
+                &config.badwords_url,
+                api_key,
+                HttpOptions::from_config(config),
+            ),
+            None => Err(Error::ConfigError(
+                "BADWORDS_API_KEY is required by the api profanity filter".to_string(),
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl ProfanityFilter for ApiLayerFilter {
+    async fn censor(&self, content: String) -> Result<Censored, Error> {
+        let res = self
+            .client
+            .post(format!("{}/bad_words?censor_character=*", self.url))
+            .header("apikey", &self.api_key)
+            .body(content.clone())
+            .send()
+            .await
+            .map_err(Error::MiddlewareReqwestAPIError)?;
+
+        if !res.status().is_success() {
+            return if res.status().is_client_error() {
+                let err = transform_error(res).await;
+                Err(Error::ClientError(err))
+            } else {
+                let err = transform_error(res).await;
+                Err(Error::ServerError(err))
+            };
+        }
+
+        match res.json::<BadWordResponse>().await {
+            // The API leaves `censored_content` empty when it found nothing.
+            Ok(res) if res.bad_words_total == 0 => Ok(Censored::unchanged(content)),
+            Ok(res) => Ok(Censored {
+                content: res.censored_content,
+                bad_words: res
+                    .bad_words_list
+                    .into_iter()
+                    .map(|word| word.original)
+                    .collect(),
+            }),
+            Err(err) => Err(Error::ReqwestAPIError(err)),
+        }
+    }
+}
+
+async fn transform_error(res: reqwest::Response) -> handle_errors::APILayerError {
+    let status = res.status().as_u16();
+    let body = res.text().await.unwrap_or_default();
+    api_error(status, body)
+}
+
+/// Takes the message out of an error body of the API. Other bodies, like an
+/// HTML page from a proxy in between, are kept as they are.
+fn api_error(status: u16, body: String) -> handle_errors::APILayerError {
+    let message = match serde_json::from_str::<APIResponse>(&body) {
+        Ok(res) => res.message,
+        Err(_) => body,
+    };
+    handle_errors::APILayerError { status, message }
+}
+
+#[cfg(test)]
+mod api_layer_tests {
+    use mock_server::{MockServer, OneshotHandler};
+
+    use super::{api_error, ApiLayerFilter, Duration, HttpOptions, ProfanityFilter};
+
+    #[tokio::test]
+    async fn run() {
+        let handler = run_mock();
+        censor_profane_words().await;
+        no_profane_words().await;
+        let _ = handler.sender.send(1);
+    }
+
+    #[test]
+    fn keeps_error_bodies_that_are_not_json() {
+        let err = api_error(401, r#"{"message": "Invalid API key"}"#.to_string());
+        assert_eq!((err.status, err.message.as_str()), (401, "Invalid API key"));
+
+        let err = api_error(502, "<html>Bad Gateway</html>".to_string());
+        assert_eq!(err.message, "<html>Bad Gateway</html>");
+    }
+
+    fn run_mock() -> OneshotHandler {
+        let socket = "127.0.0.1:3030"
+            .to_string()
+            .parse()
+            .expect("Not a valid address");
+        let mock = MockServer::new(socket);
+
+        mock.oneshot()
+    }
+
+    fn filter() -> ApiLayerFilter {
+        let options = HttpOptions {
+            connect_timeout: Duration::from_secs(1),
+            timeout: Duration::from_secs(5),
+            max_retries: 0,
+        };
+        ApiLayerFilter::new("http://127.0.0.1:3030", "YES", options).unwrap()
+    }
+
+    async fn censor_profane_words() {
+        let content = "This is a shitty sentence".to_string();
+        let censored_content = filter().censor(content).await;
+        let censored = censored_content.unwrap();
+        assert_eq!(censored.content, "this is a ****** sentence");
+        assert_eq!(censored.bad_words, ["shitty"]);
+    }
+
+    async fn no_profane_words() {
+        // The mock answers like the real API, with an empty
+        // `censored_content`, so clean text has to come back as sent.
+        let content = "This is a Sentence".to_string();
+        let censored_content = filter().censor(content).await;
+        let censored = censored_content.unwrap();
+        assert_eq!(censored.content, "This is a Sentence");
+        assert!(censored.bad_words.is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// In `HalfOpen` a single trial call is let through after the cooldown.
+/// Should it never report back, another one is allowed after the next
+/// cooldown.
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { since: Instant },
+}
+
+/// Stops calling a failing service for a while, so writes don't all wait on
+/// timeouts and retries while it is down.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    /// Consecutive failures that open the breaker.
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+/// Breaker state as shown on the health endpoint.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BreakerStatus {
+    pub state: &'static str,
+    pub consecutive_failures: u32,
+    /// Seconds until a trial call is let through, while open.
+    pub retry_in_seconds: Option<u64>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            threshold: threshold.max(1),
+            cooldown,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a call may go out now.
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } if now >= until => {
+                *state = State::HalfOpen { since: now };
+                true
+            }
+            State::HalfOpen { since } if now >= since + self.cooldown => {
+                *state = State::HalfOpen { since: now };
+                true
+            }
+            State::Open { .. } | State::HalfOpen { .. } => false,
+        }
+    }
+
+    pub fn record_success(&self) {
+        *self.state.lock().unwrap() = State::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        *state = match *state {
+            State::Closed { failures } if failures + 1 < self.threshold => State::Closed {
+                failures: failures + 1,
+            },
+            _ => State::Open {
+                until: Instant::now() + self.cooldown,
+            },
+        };
+    }
+
+    pub fn status(&self) -> BreakerStatus {
+        match *self.state.lock().unwrap() {
+            State::Closed { failures } => BreakerStatus {
+                state: "closed",
+                consecutive_failures: failures,
+                retry_in_seconds: None,
+            },
+            State::Open { until } => BreakerStatus {
+                state: "open",
+                consecutive_failures: self.threshold,
+                retry_in_seconds: Some(until.saturating_duration_since(Instant::now()).as_secs()),
+            },
+            State::HalfOpen { .. } => BreakerStatus {
+                state: "half_open",
+                consecutive_failures: self.threshold,
+                retry_in_seconds: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod breaker_tests {
+    use super::{CircuitBreaker, Duration};
+
+    #[test]
+    fn opens_after_threshold_and_closes_on_success() {
+        let breaker = CircuitBreaker::new(2, Duration::ZERO);
+        breaker.record_failure();
+        assert_eq!(breaker.status().state, "closed");
+        assert!(breaker.allow());
+        breaker.record_failure();
+        assert_eq!(breaker.status().state, "open");
+
+        // The cooldown is over at once, so one trial call goes out.
+        assert!(breaker.allow());
+        assert_eq!(breaker.status().state, "half_open");
+        breaker.record_success();
+        assert_eq!(breaker.status().state, "closed");
+        assert_eq!(breaker.status().consecutive_failures, 0);
+    }
+
+    #[test]
+    fn rejects_calls_while_open() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        breaker.record_failure();
+        assert!(!breaker.allow());
+        assert_eq!(breaker.status().retry_in_seconds, Some(59));
+    }
+
+    #[test]
+    fn failed_trial_reopens() {
+        let breaker = CircuitBreaker::new(3, Duration::ZERO);
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        assert!(breaker.allow());
+        breaker.record_failure();
+        assert_eq!(breaker.status().state, "open");
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use super::Censored;
+
+type Key = [u8; 32];
+
+#[derive(Debug, Default)]
+struct Entries {
+    censored: HashMap<Key, (Censored, Instant)>,
+    /// Keys in insertion order, which is also expiry order. A key that was
+    /// inserted again is only dropped with its newest insertion.
+    order: VecDeque<(Key, Instant)>,
+}
+
+/// Censored content by hash of the original, so unchanged text isn't sent to
+/// the filter again. Holds at most `capacity` entries, each for `ttl`.
+#[derive(Debug)]
+pub struct ProfanityCache {
+    capacity: usize,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Cache counters as shown on the health endpoint.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+fn key(content: &str) -> Key {
+    Sha256::digest(content.as_bytes()).into()
+}
+
+impl ProfanityCache {
+    /// A cache with a capacity of 0 stores nothing.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        ProfanityCache {
+            capacity,
+            ttl,
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, content: &str) -> Option<Censored> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let entries = self.entries.lock().unwrap();
+        match entries.censored.get(&key(content)) {
+            Some((censored, inserted)) if inserted.elapsed() < self.ttl => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(censored.clone())
+            }
+            _ => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, content: &str, censored: Censored) {
+        if self.capacity == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        // Drop expired entries, then the oldest ones until there is room.
+        while let Some(&(oldest, inserted)) = entries.order.front() {
+            if now.duration_since(inserted) < self.ttl && entries.censored.len() < self.capacity {
+                break;
+            }
+            entries.order.pop_front();
+            if entries.censored.get(&oldest).map(|(_, at)| *at) == Some(inserted) {
+                entries.censored.remove(&oldest);
+            }
+        }
+        let key = key(content);
+        entries.censored.insert(key, (censored, now));
+        entries.order.push_back((key, now));
+    }
+
+    /// Empties the cache. The counters keep running.
+    pub fn flush(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let flushed = entries.censored.len();
+        *entries = Entries::default();
+        flushed
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.lock().unwrap().censored.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod cache_tests {
+    use super::{CacheStats, Censored, Duration, ProfanityCache};
+
+    fn censored(content: &str) -> Censored {
+        Censored {
+            content: content.to_string(),
+            bad_words: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let cache = ProfanityCache::new(10, Duration::from_secs(60));
+        assert_eq!(cache.get("shitty"), None);
+        cache.insert("shitty", censored("******"));
+        assert_eq!(cache.get("shitty"), Some(censored("******")));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                entries: 1,
+                hits: 1,
+                misses: 1
+            }
+        );
+        assert_eq!(cache.flush(), 1);
+        assert_eq!(cache.get("shitty"), None);
+    }
+
+    #[test]
+    fn evicts_the_oldest_entry_when_full() {
+        let cache = ProfanityCache::new(2, Duration::from_secs(60));
+        cache.insert("a", censored("a"));
+        cache.insert("b", censored("b"));
+        cache.insert("c", censored("c"));
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("c"), Some(censored("c")));
+    }
+
+    #[test]
+    fn expired_entries_miss() {
+        let cache = ProfanityCache::new(2, Duration::ZERO);
+        cache.insert("a", censored("a"));
+        assert_eq!(cache.get("a"), None);
+    }
+
+    #[test]
+    fn zero_capacity_disables_the_cache() {
+        let cache = ProfanityCache::new(0, Duration::from_secs(60));
+        cache.insert("a", censored("a"));
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.stats().misses, 0);
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+use std::fs;
+
+use async_trait::async_trait;
+use handle_errors::Error;
+
+use super::{Censored, ProfanityFilter};
+use crate::config::Config;
+
+/// Used when no wordlist file is configured.
+const DEFAULT_WORDS: &str = include_str!("words.txt");
+
+/// Censors words found in a local list, without calling out to any service.
+/// Matching ignores case and undoes simple leetspeak like `$h1t`.
+#[derive(Debug, Clone)]
+pub struct WordlistFilter {
+    words: HashSet<String>,
+}
+
+impl WordlistFilter {
+    /// Builds a filter from one word per line. Empty lines and lines
+    /// starting with `#` are skipped.
+    pub fn new(list: &str) -> WordlistFilter {
+        WordlistFilter {
+            words: list
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(normalize)
+                .collect(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<WordlistFilter, Error> {
+        match &config.profanity_wordlist {
+            Some(path) => fs::read_to_string(path)
+                .map(|list| WordlistFilter::new(&list))
+                .map_err(|err| {
+                    Error::ConfigError(format!("cannot read wordlist {}: {}", path, err))
+                }),
+            None => Ok(WordlistFilter::new(DEFAULT_WORDS)),
+        }
+    }
+
+    /// Masks every listed word in the content, leaving the rest untouched.
+    pub fn censor_text(&self, content: &str) -> Censored {
+        let mut censored = Censored {
+            content: String::with_capacity(content.len()),
+            bad_words: Vec::new(),
+        };
+        let mut token = String::new();
+        for c in content.chars() {
+            if is_word_char(c) {
+                token.push(c);
+            } else {
+                self.push_token(&mut censored, &token);
+                token.clear();
+                censored.content.push(c);
+            }
+        }
+        self.push_token(&mut censored, &token);
+        censored
+    }
+
+    fn push_token(&self, out: &mut Censored, token: &str) {
+        if self.words.contains(&normalize(token)) {
+            out.content.extend(token.chars().map(|_| '*'));
+            out.bad_words.push(token.to_string());
+            return;
+        }
+        // Leet symbols also work as punctuation, as in "shit!", so try again
+        // without the ones around the word.
+        let core = token.trim_matches(|c: char| !c.is_alphanumeric());
+        if !core.is_empty() && core.len() != token.len() && self.words.contains(&normalize(core)) {
+            let start = token.find(core).unwrap_or(0);
+            out.content.push_str(&token[..start]);
+            out.content.extend(core.chars().map(|_| '*'));
+            out.content.push_str(&token[start + core.len()..]);
+            out.bad_words.push(core.to_string());
+            return;
+        }
+        out.content.push_str(token);
+    }
+}
+
+#[async_trait]
+impl ProfanityFilter for WordlistFilter {
+    async fn censor(&self, content: String) -> Result<Censored, Error> {
+        Ok(self.censor_text(&content))
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '@' | '$' | '!')
+}
+
+/// Lowercases a word and maps the usual leetspeak stand-ins back to letters.
+fn normalize(word: &str) -> String {
+    word.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            '0' => 'o',
+            '1' | '!' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' => 't',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod wordlist_tests {
+    use super::{WordlistFilter, DEFAULT_WORDS};
+
+    #[test]
+    fn censors_listed_words_ignoring_case() {
+        let filter = WordlistFilter::new(DEFAULT_WORDS);
+        assert_eq!(
+            filter.censor_text("This is a Shitty sentence").content,
+            "This is a ****** sentence"
+        );
+        assert_eq!(
+            filter.censor_text("this is a sentence").content,
+            "this is a sentence"
+        );
+        assert_eq!(
+            filter.censor_text("class assignment").content,
+            "class assignment"
+        );
+    }
+
+    #[test]
+    fn censors_leetspeak() {
+        let filter = WordlistFilter::new(DEFAULT_WORDS);
+        assert_eq!(
+            filter.censor_text("what a $h1t day").content,
+            "what a **** day"
+        );
+        assert_eq!(filter.censor_text("B!tch, please").content, "*****, please");
+        assert_eq!(filter.censor_text("oh shit!").content, "oh ****!");
+        assert_eq!(
+            filter.censor_text("I have 5 apples!").content,
+            "I have 5 apples!"
+        );
+    }
+
+    #[test]
+    fn reports_the_words_found() {
+        let filter = WordlistFilter::new(DEFAULT_WORDS);
+        let censored = filter.censor_text("Sh1t, what a damn day!");
+        assert_eq!(censored.bad_words, ["Sh1t", "damn"]);
+        assert!(filter.censor_text("a fine day").bad_words.is_empty());
+    }
+
+    #[test]
+    fn uses_the_given_list() {
+        let filter = WordlistFilter::new("# custom list\nfoo\n\n  Bar  \n");
+        assert_eq!(filter.censor_text("foo bar baz").content, "*** *** baz");
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use warp::{Filter, Rejection};
+
+use crate::account::Session;
+
+/// Bucket count above which idle, refilled buckets are dropped.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket rate limiter. Each key gets `capacity` requests as a burst,
+/// refilled continuously at `capacity` per minute.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn per_minute(limit: u32) -> Self {
+        RateLimiter {
+            capacity: limit as f64,
+            refill_per_sec: limit as f64 / 60.0,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Takes a token from the key's bucket. On failure returns the number of
+    /// seconds until a token is available again.
+    pub fn check(&self, key: &str) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            let (capacity, refill) = (self.capacity, self.refill_per_sec);
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * refill < capacity
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if self.refill_per_sec > 0.0 {
+            Err(((1.0 - bucket.tokens) / self.refill_per_sec).ceil() as u64)
+        } else {
+            Err(60)
+        }
+    }
+}
+
+/// Limits requests per client IP.
+pub fn by_ip(limiter: RateLimiter) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and_then(move |addr: Option<std::net::SocketAddr>| {
+            let limiter = limiter.clone();
+            async move {
+                let key = match addr {
+                    Some(addr) => format!("ip:{}", addr.ip()),
+                    None => "ip:unknown".to_string(),
+                };
+                limiter.check(&key).map_err(|retry_after| {
+                    warp::reject::custom(handle_errors::Error::TooManyRequests(retry_after))
+                })
+            }
+        })
+        .untuple_one()
+}
+
+/// Limits requests per authenticated account. Wraps the `auth()` filter so
+/// the session is checked before the handler runs.
+pub fn by_account(
+    limiter: RateLimiter,
+    auth: impl Filter<Extract = (Session,), Error = Rejection> + Clone,
+) -> impl Filter<Extract = (Session,), Error = Rejection> + Clone {
+    auth.and_then(move |session: Session| {
+        let limiter = limiter.clone();
+        async move {
+            match limiter.check(&format!("account:{}", session.account_id.0)) {
+                Ok(()) => Ok(session),
+                Err(retry_after) => Err(warp::reject::custom(
+                    handle_errors::Error::TooManyRequests(retry_after),
+                )),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use super::RateLimiter;
+
+    #[test]
+    fn exhausts_burst_then_asks_to_retry() {
+        let limiter = RateLimiter::per_minute(2);
+
+        assert!(limiter.check("ip:127.0.0.1").is_ok());
+        assert!(limiter.check("ip:127.0.0.1").is_ok());
+        // One token refills every 30 seconds.
+        assert_eq!(limiter.check("ip:127.0.0.1"), Err(30));
+    }
+
+    #[test]
+    fn keys_have_separate_buckets() {
+        let limiter = RateLimiter::per_minute(1);
+
+        assert!(limiter.check("account:1").is_ok());
+        assert!(limiter.check("account:1").is_err());
+        assert!(limiter.check("account:2").is_ok());
+    }
+}
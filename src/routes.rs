@@ -1,3 +1,11 @@
 pub mod answer;
+pub mod api_key;
 pub mod authentication;
+pub mod health;
+pub mod invite;
+pub mod moderation;
+pub mod oidc;
+pub mod organization;
+pub mod profanity;
 pub mod question;
+pub mod two_factor;
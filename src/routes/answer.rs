@@ -2,27 +2,53 @@ use warp::http::StatusCode;
 use warp::{Rejection, Reply};
 
 use crate::account::Session;
-use crate::profanity::check_profanity;
+use crate::profanity::{Field, ProfanityService};
 use crate::store::Store;
 use crate::types::answer::NewAnswer;
+use crate::types::api_key::Scope;
+use crate::types::moderation::ModerationStatus;
 
 pub async fn add_answer(
     session: Session,
     store: Store,
+    profanity: ProfanityService,
     new_answer: NewAnswer,
 ) -> Result<impl Reply, Rejection> {
+    if !session.has_scope(Scope::AnswersWrite) {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
     let account_id = session.account_id;
-    let content = match check_profanity(new_answer.content).await {
-        Ok(res) => res,
+    if !store
+        .can_view_question(new_answer.question_id.0, &account_id)
+        .await?
+    {
+        return Err(warp::reject::custom(handle_errors::Error::QuestionNotFound));
+    }
+    let content = match profanity.submit_answer(new_answer.content).await {
+        Ok(content) => content,
         Err(err) => return Err(warp::reject::custom(err)),
     };
+    let audit = content.audit(Field::AnswerContent);
     let answer = NewAnswer {
-        content,
+        content: content.content,
         question_id: new_answer.question_id,
     };
 
-    match store.add_answer(answer, account_id).await {
-        Ok(_) => Ok(warp::reply::with_status("Answer added", StatusCode::OK)),
-        Err(err) => Err(warp::reject::custom(err)),
+    let answer = match store.add_answer(answer, account_id, content.status).await {
+        Ok(answer) => answer,
+        Err(err) => return Err(warp::reject::custom(err)),
+    };
+    if let Some(audit) = audit {
+        store
+            .add_profanity_audits(None, Some(&answer.id), vec![audit])
+            .await?;
+    }
+    match answer.status {
+        ModerationStatus::Approved => Ok(warp::reply::with_status("Answer added", StatusCode::OK)),
+        ModerationStatus::Pending | ModerationStatus::Flagged | ModerationStatus::Rejected => Ok(
+            warp::reply::with_status("Answer awaits moderation", StatusCode::ACCEPTED),
+        ),
     }
 }
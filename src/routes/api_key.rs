@@ -0,0 +1,81 @@
+use rand::random;
+use warp::http::StatusCode;
+use warp::{Rejection, Reply};
+
+use crate::account::Session;
+use crate::routes::authentication::hash_token;
+use crate::store::Store;
+use crate::types::api_key::{ApiKeyId, CreatedApiKey, NewApiKey};
+
+/// Every API key starts with this, which is how `auth()` tells it apart
+/// from a PASETO token.
+pub const API_KEY_PREFIX: &str = "qna_";
+
+pub async fn add_api_key(
+    session: Session,
+    store: Store,
+    new_key: NewApiKey,
+) -> Result<impl Reply, Rejection> {
+    // Keys can only be managed with a full session, so a leaked key can't
+    // mint more keys.
+    if session.is_api_key() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
+
+    let prefix = hex::encode(random::<[u8; 4]>());
+    let key = format!(
+        "{}{}_{}",
+        API_KEY_PREFIX,
+        prefix,
+        hex::encode(random::<[u8; 32]>())
+    );
+    let id = store
+        .add_api_key(&session.account_id, &new_key, &prefix, &hash_token(&key))
+        .await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&CreatedApiKey {
+            id,
+            name: new_key.name,
+            key,
+            scopes: new_key.scopes,
+        }),
+        StatusCode::CREATED,
+    ))
+}
+
+pub async fn get_api_keys(session: Session, store: Store) -> Result<impl Reply, Rejection> {
+    if session.is_api_key() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
+    match store.get_api_keys(&session.account_id).await {
+        Ok(keys) => Ok(warp::reply::json(&keys)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+pub async fn delete_api_key(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl Reply, Rejection> {
+    if session.is_api_key() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
+    if !store
+        .revoke_api_key(&ApiKeyId(id), &session.account_id)
+        .await?
+    {
+        return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
+    }
+    Ok(warp::reply::with_status(
+        format!("API key: {} revoked", id),
+        StatusCode::OK,
+    ))
+}
@@ -1,23 +1,78 @@
-use std::env;
-use std::future;
+use std::net::SocketAddr;
 
-use argon2::Config;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use handle_errors::FieldError;
 use pasetors::{
     claims::{Claims, ClaimsValidationRules},
-    keys::SymmetricKey,
-    token::UntrustedToken,
+    footer::Footer,
+    token::{TrustedToken, UntrustedToken},
     version4::V4,
-    Local,
+    Local, Public,
 };
 use rand::random;
-use warp::Filter;
+use sha2::{Digest, Sha256};
+use warp::http::{header::SET_COOKIE, HeaderValue, Method, StatusCode};
+use warp::{Filter, Reply};
 
-use crate::account::{Account, AccountId, Session};
+use crate::account::{
+    Account, AccountId, LoginChallenge, LogoutRequest, PasswordChange, RefreshRequest,
+    Registration, Role, Session, Tokens,
+};
+use crate::config::RegistrationMode;
+use crate::keyring::{Key, Keyring};
+use crate::lockout::LockoutPolicy;
+use crate::password::PasswordHasher;
+use crate::profanity::ProfanityService;
+use crate::routes::api_key::API_KEY_PREFIX;
 use crate::store::Store;
+use crate::validation::{normalize_email, validate_new_password, validate_registration};
+
+/// Lifetime of an access token. Kept short since access tokens are only
+/// revocable through the denylist.
+const ACCESS_TOKEN_TTL_MINUTES: i64 = 15;
+/// Lifetime of a refresh token. Each use rotates it for a new one.
+const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+/// Number of entries returned by the login history.
+const LOGIN_HISTORY_LENGTH: i64 = 50;
+
+/// Cookie carrying the access token of a browser session.
+pub const SESSION_COOKIE: &str = "qna_session";
+/// Cookie carrying the refresh token of a browser session.
+pub const REFRESH_COOKIE: &str = "qna_refresh";
+/// Cookie carrying the CSRF token. Not HttpOnly, so the frontend can read it
+/// and echo it in the `X-CSRF-Token` header.
+pub const CSRF_COOKIE: &str = "qna_csrf";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
 
-pub async fn register(store: Store, account: Account) -> Result<impl warp::Reply, warp::Rejection> {
-    let hashed_password = hash_password(account.password.as_bytes());
+/// Creates an account, as far as the registration mode allows. In
+/// invite-only mode every sign-up takes one use of an invite code.
+pub async fn register(
+    store: Store,
+    hasher: PasswordHasher,
+    mode: RegistrationMode,
+    profanity: ProfanityService,
+    registration: Registration,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if mode == RegistrationMode::Closed {
+        return Err(warp::reject::custom(
+            handle_errors::Error::RegistrationClosed,
+        ));
+    }
+    let account = validate_registration(registration.account)?;
+    profanity.check_email(&account.email)?;
+
+    let invite = match (mode, registration.invite_code) {
+        (RegistrationMode::InviteOnly, Some(code)) => {
+            match store.claim_invite(&hash_token(code.trim())).await? {
+                Some(invite) => Some(invite),
+                None => return Err(invalid_invite("is invalid or expired")),
+            }
+        }
+        (RegistrationMode::InviteOnly, None) => return Err(invalid_invite("is required")),
+        _ => None,
+    };
+
+    let hashed_password = hasher.hash(account.password.as_bytes());
     let account = Account {
         id: account.id,
         email: account.email,
@@ -26,95 +81,678 @@ pub async fn register(store: Store, account: Account) -> Result<impl warp::Reply
 
     match store.add_account(account).await {
         Ok(_) => Ok(warp::reply::json(&"Account added")),
-        Err(err) => Err(warp::reject::custom(err)),
+        Err(err) => {
+            // A failed sign-up, e.g. with a taken email, doesn't use up the invite.
+            if let Some(invite) = invite {
+                store.release_invite(&invite).await?;
+            }
+            Err(warp::reject::custom(err))
+        }
     }
 }
 
-pub async fn login(store: Store, login: Account) -> Result<impl warp::Reply, warp::Rejection> {
-    let result = store.get_account(login.email).await;
-    match result {
-        Ok(account) => match verify_password(&account.password, login.password.as_bytes()) {
-            Ok(verified) => {
-                if verified {
-                    Ok(warp::reply::json(&issue_token(
-                        account.id.expect("id not found"),
-                    )))
-                } else {
-                    Err(warp::reject::custom(handle_errors::Error::WrongPassword))
-                }
+fn invalid_invite(message: &str) -> warp::Rejection {
+    warp::reject::custom(handle_errors::Error::ValidationError(vec![FieldError {
+        field: "invite_code".to_string(),
+        message: message.to_string(),
+    }]))
+}
+
+/// Logs in with email and password. Every attempt is recorded with the
+/// client IP, and repeated failures lock the email out with exponential
+/// backoff. Accounts with 2FA get a challenge instead of tokens, see
+/// `two_factor::verify_login`.
+pub async fn login(
+    store: Store,
+    keyring: Keyring,
+    hasher: PasswordHasher,
+    policy: LockoutPolicy,
+    remote: Option<SocketAddr>,
+    login: Account,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ip = remote.map(|addr| addr.ip().to_string());
+    // Emails are stored normalized on registration.
+    let login = Account {
+        email: normalize_email(&login.email),
+        ..login
+    };
+
+    reject_if_locked(&store, &login.email, ip.as_deref()).await?;
+
+    // An unknown email goes through the same steps as a wrong password,
+    // including an argon2 verification against a dummy hash, so neither the
+    // response nor its timing tells whether the email is registered.
+    let account = store.get_account(login.email.clone()).await?;
+    let (account_id, hash) = match &account {
+        Some(account) => (account.id.as_ref(), account.password.as_str()),
+        None => (None, hasher.dummy_hash()),
+    };
+    let verified = PasswordHasher::verify(hash, login.password.as_bytes())
+        .map_err(handle_errors::Error::ArgonLibraryError)?;
+
+    match account_id {
+        Some(account_id) if verified => {
+            // The plaintext is only available here, so this is the moment to
+            // move hashes made with older parameters to the current ones.
+            if hasher.needs_rehash(hash) {
+                let rehashed = hasher.hash(login.password.as_bytes());
+                store.rehash_password(account_id, rehashed).await?;
+            }
+            finish_first_factor(&store, &keyring, &login.email, account_id, ip.as_deref()).await
+        }
+        _ => {
+            record_failed_login(&store, policy, &login.email, account_id, ip.as_deref()).await?;
+            Err(warp::reject::custom(handle_errors::Error::WrongPassword))
+        }
+    }
+}
+
+/// Rejects a login for an email that is locked out, or from a client IP
+/// that is, recording the attempt.
+pub async fn reject_if_locked(
+    store: &Store,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<(), warp::Rejection> {
+    if let Some(locked_until) = store.get_lockout(email, ip).await? {
+        store.add_login_attempt(email, None, ip, false).await?;
+        let retry_after = (locked_until - Utc::now()).num_seconds().max(1) as u64;
+        return Err(warp::reject::custom(handle_errors::Error::AccountLocked(
+            retry_after,
+        )));
+    }
+    Ok(())
+}
+
+/// Records a failed login against the account and the client IP, and locks
+/// either out once the policy says so.
+pub async fn record_failed_login(
+    store: &Store,
+    policy: LockoutPolicy,
+    email: &str,
+    account_id: Option<&AccountId>,
+    ip: Option<&str>,
+) -> Result<(), warp::Rejection> {
+    store
+        .add_login_attempt(email, account_id, ip, false)
+        .await?;
+    let failures = store.add_login_failure(email, "", policy.base).await?;
+    if let Some(duration) = policy.lock_duration(failures) {
+        store.set_lockout(email, "", lock_until(duration)).await?;
+    }
+    if let Some(ip) = ip {
+        let failures = store.add_login_failure("", ip, policy.base).await?;
+        if let Some(duration) = policy.ip_lock_duration(failures) {
+            store.set_lockout("", ip, lock_until(duration)).await?;
+        }
+    }
+    Ok(())
+}
+
+fn lock_until(duration: std::time::Duration) -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::from_std(duration).expect("Lockout out of range")
+}
+
+/// Ends a login whose first factor, a password or an SSO identity, checked
+/// out. With 2FA it only earns a challenge, which is traded for tokens
+/// together with a code; otherwise the tokens are issued right away.
+pub async fn finish_first_factor(
+    store: &Store,
+    keyring: &Keyring,
+    email: &str,
+    account_id: &AccountId,
+    ip: Option<&str>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if store.get_two_factor(account_id).await?.enabled {
+        let challenge = hex::encode(random::<[u8; 32]>());
+        store
+            .add_login_challenge(&hash_token(&challenge), account_id)
+            .await?;
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&LoginChallenge { challenge }),
+            StatusCode::ACCEPTED,
+        )
+        .into_response());
+    }
+    let tokens = complete_login(store, keyring, email, account_id, ip).await?;
+    Ok(with_session_cookies(warp::reply::json(&tokens), &tokens))
+}
+
+/// Records a successful login and issues its tokens.
+pub async fn complete_login(
+    store: &Store,
+    keyring: &Keyring,
+    email: &str,
+    account_id: &AccountId,
+    ip: Option<&str>,
+) -> Result<Tokens, warp::Rejection> {
+    store.clear_lockout(email).await?;
+    store
+        .add_login_attempt(email, Some(account_id), ip, true)
+        .await?;
+    issue_tokens(store, keyring, account_id.clone()).await
+}
+
+/// Lists the most recent login attempts on the logged in account.
+pub async fn login_history(
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if session.is_api_key() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
+    let attempts = store
+        .get_login_attempts(&session.account_id, LOGIN_HISTORY_LENGTH)
+        .await?;
+    Ok(warp::reply::json(&attempts))
+}
+
+/// Rejects sessions that don't belong to an admin. API keys never count,
+/// and admin actions need an account protected by a second factor.
+pub async fn require_admin(session: &Session, store: &Store) -> Result<(), warp::Rejection> {
+    if session.is_api_key()
+        || store.get_role(&session.account_id).await? != Role::Admin
+        || !store.get_two_factor(&session.account_id).await?.enabled
+    {
+        return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
+    }
+    Ok(())
+}
+
+/// Rejects sessions that don't belong to a moderator or an admin. API keys
+/// never count.
+pub async fn require_moderator(session: &Session, store: &Store) -> Result<(), warp::Rejection> {
+    if session.is_api_key()
+        || !matches!(
+            store.get_role(&session.account_id).await?,
+            Role::Moderator | Role::Admin
+        )
+    {
+        return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
+    }
+    Ok(())
+}
+
+/// Lets an admin lift the lockout of an account before it runs out.
+pub async fn clear_lockout(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    require_admin(&session, &store).await?;
+    let account = store.get_account_by_id(&AccountId(id)).await?;
+    store.clear_lockout(&account.email).await?;
+    Ok(warp::reply::json(&"Lockout cleared"))
+}
+
+/// Exchanges a refresh token for a new token pair. The presented refresh
+/// token is revoked; presenting it again revokes every refresh token of the
+/// account, since that means it has leaked. Browsers send the token as a
+/// cookie instead of in the body.
+pub async fn refresh(
+    store: Store,
+    keyring: Keyring,
+    refresh_cookie: Option<String>,
+    request: RefreshRequest,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let refresh_token = match request.refresh_token.or(refresh_cookie) {
+        Some(refresh_token) => refresh_token,
+        None => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::InvalidRefreshToken,
+            ))
+        }
+    };
+    let token = match store.get_refresh_token(&hash_token(&refresh_token)).await? {
+        Some(token) => token,
+        None => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::InvalidRefreshToken,
+            ))
+        }
+    };
+
+    if token.revoked || !store.revoke_refresh_token(token.id).await? {
+        tracing::event!(
+            tracing::Level::WARN,
+            account_id = token.account_id.0,
+            "Refresh token reused, revoking all sessions"
+        );
+        store.revoke_refresh_tokens(&token.account_id).await?;
+        return Err(warp::reject::custom(
+            handle_errors::Error::InvalidRefreshToken,
+        ));
+    }
+    if token.expires_at < Utc::now() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InvalidRefreshToken,
+        ));
+    }
+
+    let tokens = issue_tokens(&store, &keyring, token.account_id).await?;
+    Ok(with_session_cookies(warp::reply::json(&tokens), &tokens))
+}
+
+/// Revokes the access token used for the request and, if given, the
+/// refresh token belonging to the same session. Browser session cookies are
+/// cleared.
+pub async fn logout(
+    session: Session,
+    store: Store,
+    refresh_cookie: Option<String>,
+    request: LogoutRequest,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if session.is_api_key() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
+    store.revoke_token(&session.jti, session.exp).await?;
+
+    if let Some(refresh_token) = request.refresh_token.or(refresh_cookie) {
+        if let Some(token) = store.get_refresh_token(&hash_token(&refresh_token)).await? {
+            if token.account_id == session.account_id {
+                store.revoke_refresh_token(token.id).await?;
             }
-            Err(err) => Err(warp::reject::custom(
+        }
+    }
+
+    Ok(without_session_cookies(warp::reply::json(&"Logged out")))
+}
+
+/// Changes the password of the logged in account. Bumping the token version
+/// ends every existing session, including the one making the request.
+pub async fn change_password(
+    session: Session,
+    store: Store,
+    hasher: PasswordHasher,
+    change: PasswordChange,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if session.is_api_key() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
+    let account = store.get_account_by_id(&session.account_id).await?;
+    match PasswordHasher::verify(&account.password, change.current_password.as_bytes()) {
+        Ok(true) => {}
+        Ok(false) => return Err(warp::reject::custom(handle_errors::Error::WrongPassword)),
+        Err(err) => {
+            return Err(warp::reject::custom(
                 handle_errors::Error::ArgonLibraryError(err),
-            )),
-        },
-        Err(err) => Err(warp::reject::custom(err)),
+            ))
+        }
     }
+    validate_new_password(&change.new_password, &account.email)?;
+
+    let hashed_password = hasher.hash(change.new_password.as_bytes());
+    store
+        .update_password(&session.account_id, hashed_password)
+        .await?;
+    store.revoke_refresh_tokens(&session.account_id).await?;
+
+    Ok(warp::reply::json(&"Password changed"))
 }
 
-pub fn hash_password(password: &[u8]) -> String {
-    let salt = random::<[u8; 32]>();
-    let config = Config::default();
-    argon2::hash_encoded(password, &salt, &config).unwrap()
+/// Refresh tokens are stored as SHA-256 digests, so a leaked table cannot be
+/// replayed against the API.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
 
-fn verify_password(hash: &str, password: &[u8]) -> Result<bool, argon2::Error> {
-    argon2::verify_encoded(hash, password)
+pub async fn issue_tokens(
+    store: &Store,
+    keyring: &Keyring,
+    account_id: AccountId,
+) -> Result<Tokens, warp::Rejection> {
+    let refresh_token = hex::encode(random::<[u8; 32]>());
+    let expires_at = Utc::now() + chrono::Duration::days(REFRESH_TOKEN_TTL_DAYS);
+    store
+        .add_refresh_token(&account_id, &hash_token(&refresh_token), expires_at)
+        .await?;
+
+    let token_version = store.get_token_version(&account_id).await?;
+
+    Ok(Tokens {
+        access_token: issue_token(keyring, account_id, token_version),
+        refresh_token,
+    })
 }
 
-fn issue_token(account_id: AccountId) -> String {
+fn issue_token(keyring: &Keyring, account_id: AccountId, token_version: i32) -> String {
     let current_date_time = Utc::now();
-    let dt = current_date_time + chrono::Duration::days(1);
-    let key = env::var("PASETO_KEY").expect("Env var PASETO_KEY must be set");
+    let dt = current_date_time + chrono::Duration::minutes(ACCESS_TOKEN_TTL_MINUTES);
+    let (kid, key) = keyring.current();
 
-    let key = SymmetricKey::<V4>::from(key.as_bytes()).expect("Invalid PASETO_KEY");
+    let mut footer = Footer::new();
+    footer
+        .parse_string(&serde_json::json!({ "kid": kid }).to_string())
+        .expect("Failed to set footer 'kid'");
     let mut claims = Claims::new().unwrap();
     claims
         .add_additional("account_id", serde_json::json!(account_id))
         .expect("Failed to set claim 'account_id'");
+    claims
+        .add_additional("token_version", token_version)
+        .expect("Failed to set claim 'token_version'");
+    claims
+        .token_identifier(&uuid::Uuid::new_v4().to_string())
+        .expect("Failed to set claim 'jti'");
     claims
         .expiration(&dt.to_rfc3339())
         .expect("Failed to set claim 'exp'");
     claims
         .not_before(&Utc::now().to_rfc3339())
         .expect("Failed to set claim 'nbf'");
-    pasetors::local::encrypt(&key, &claims, None, None)
-        .expect("Failed to construct paseto token with builder!")
+    match key {
+        Key::Local(key) => pasetors::local::encrypt(key, &claims, Some(&footer), None),
+        Key::Public(secret_key, _) => {
+            pasetors::public::sign(secret_key, &claims, Some(&footer), None)
+        }
+    }
+    .expect("Failed to construct paseto token with builder!")
+}
+
+/// Sets the cookies of a browser session for a freshly issued token pair,
+/// together with a new CSRF token.
+pub fn with_session_cookies(reply: impl Reply, tokens: &Tokens) -> warp::reply::Response {
+    let csrf_token = hex::encode(random::<[u8; 32]>());
+    let cookies = [
+        session_cookie(
+            SESSION_COOKIE,
+            &tokens.access_token,
+            ACCESS_TOKEN_TTL_MINUTES * 60,
+            true,
+        ),
+        session_cookie(
+            REFRESH_COOKIE,
+            &tokens.refresh_token,
+            REFRESH_TOKEN_TTL_DAYS * 24 * 60 * 60,
+            true,
+        ),
+        session_cookie(
+            CSRF_COOKIE,
+            &csrf_token,
+            REFRESH_TOKEN_TTL_DAYS * 24 * 60 * 60,
+            false,
+        ),
+    ];
+    set_cookies(reply, &cookies)
+}
+
+/// Expires the cookies of a browser session.
+pub fn without_session_cookies(reply: impl Reply) -> warp::reply::Response {
+    let cookies = [
+        session_cookie(SESSION_COOKIE, "", 0, true),
+        session_cookie(REFRESH_COOKIE, "", 0, true),
+        session_cookie(CSRF_COOKIE, "", 0, false),
+    ];
+    set_cookies(reply, &cookies)
+}
+
+fn session_cookie(name: &str, value: &str, max_age: i64, http_only: bool) -> String {
+    format!(
+        "{}={}; Max-Age={}; Path=/; Secure; SameSite=Strict{}",
+        name,
+        value,
+        max_age,
+        if http_only { "; HttpOnly" } else { "" }
+    )
+}
+
+pub fn set_cookies(reply: impl Reply, cookies: &[String]) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    for cookie in cookies {
+        response.headers_mut().append(
+            SET_COOKIE,
+            HeaderValue::from_str(cookie).expect("Cookie is not a valid header value"),
+        );
+    }
+    response
+}
+
+/// Double-submit check for requests authenticated by cookie: the CSRF
+/// header has to repeat the CSRF cookie. A cross-site page can make the
+/// browser send the cookie, but can't read it to set the header.
+fn check_csrf(
+    method: &Method,
+    csrf_cookie: Option<&str>,
+    csrf_header: Option<&str>,
+) -> Result<(), handle_errors::Error> {
+    if method == Method::GET || method == Method::HEAD || method == Method::OPTIONS {
+        return Ok(());
+    }
+    match (csrf_cookie, csrf_header) {
+        (Some(cookie), Some(header)) if !cookie.is_empty() && constant_time_eq(cookie, header) => {
+            Ok(())
+        }
+        _ => Err(handle_errors::Error::InvalidCsrfToken),
+    }
 }
 
-pub fn verify_token(token: String) -> Result<Session, handle_errors::Error> {
-    let key = env::var("PASETO_KEY").expect("Env var PASETO_KEY must be set");
-    let key = SymmetricKey::<V4>::from(key.as_bytes()).expect("Invalid PASETO_KEY");
-    let validation_rules = ClaimsValidationRules::new();
-    let untrusted_token = UntrustedToken::<Local, V4>::try_from(&token)
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Extracts the CSRF cookie and header of a request.
+fn csrf_tokens(
+) -> impl Filter<Extract = (Option<String>, Option<String>), Error = warp::Rejection> + Clone {
+    warp::cookie::optional::<String>(CSRF_COOKIE).and(warp::header::optional::<String>(CSRF_HEADER))
+}
+
+/// Extracts the refresh token cookie of a browser session, checking the
+/// CSRF token that has to come with it.
+pub fn refresh_cookie() -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone
+{
+    warp::cookie::optional::<String>(REFRESH_COOKIE)
+        .and(warp::method())
+        .and(csrf_tokens())
+        .and_then(
+            |cookie: Option<String>,
+             method: Method,
+             csrf_cookie: Option<String>,
+             csrf_header: Option<String>| async move {
+                if cookie.is_some() {
+                    check_csrf(&method, csrf_cookie.as_deref(), csrf_header.as_deref())
+                        .map_err(warp::reject::custom)?;
+                }
+                Ok::<_, warp::Rejection>(cookie)
+            },
+        )
+}
+
+pub async fn verify_token(
+    token: String,
+    store: &Store,
+    keyring: &Keyring,
+) -> Result<Session, handle_errors::Error> {
+    let trusted_token = if token.starts_with("v4.public.") {
+        verify_public_token(&token, keyring)
+    } else {
+        verify_local_token(&token, keyring)
+    }
+    .map_err(|_| handle_errors::Error::CannotDecryptToken)?;
+
+    let session = serde_json::from_str::<Session>(trusted_token.payload())
         .map_err(|_| handle_errors::Error::CannotDecryptToken)?;
-    let trusted_token =
-        pasetors::local::decrypt(&key, &untrusted_token, &validation_rules, None, None)
-            .map_err(|_| handle_errors::Error::CannotDecryptToken)?;
+    if store.is_token_revoked(&session.jti) {
+        return Err(handle_errors::Error::CannotDecryptToken);
+    }
+    if store.get_token_version(&session.account_id).await? != session.token_version {
+        return Err(handle_errors::Error::CannotDecryptToken);
+    }
+    Ok(session)
+}
 
-    serde_json::from_str::<Session>(trusted_token.payload())
-        .map_err(|_| handle_errors::Error::CannotDecryptToken)
+fn verify_local_token(
+    token: &str,
+    keyring: &Keyring,
+) -> Result<TrustedToken, pasetors::errors::Error> {
+    let untrusted_token = UntrustedToken::<Local, V4>::try_from(token)?;
+    let footer = token_footer(untrusted_token.untrusted_footer())?;
+    match token_key(&footer, keyring)? {
+        Key::Local(key) => pasetors::local::decrypt(
+            key,
+            &untrusted_token,
+            &ClaimsValidationRules::new(),
+            Some(&footer),
+            None,
+        ),
+        Key::Public(..) => Err(pasetors::errors::Error::TokenValidation),
+    }
 }
 
-pub fn auth() -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
-    warp::header::<String>("Authorization").and_then(|token: String| {
-        let token = match verify_token(token) {
-            Ok(t) => t,
-            Err(_) => return future::ready(Err(warp::reject::reject())),
-        };
-        future::ready(Ok(token))
+fn verify_public_token(
+    token: &str,
+    keyring: &Keyring,
+) -> Result<TrustedToken, pasetors::errors::Error> {
+    let untrusted_token = UntrustedToken::<Public, V4>::try_from(token)?;
+    let footer = token_footer(untrusted_token.untrusted_footer())?;
+    match token_key(&footer, keyring)? {
+        Key::Public(_, public_key) => pasetors::public::verify(
+            public_key,
+            &untrusted_token,
+            &ClaimsValidationRules::new(),
+            Some(&footer),
+            None,
+        ),
+        Key::Local(_) => Err(pasetors::errors::Error::TokenValidation),
+    }
+}
+
+fn token_footer(untrusted_footer: &[u8]) -> Result<Footer, pasetors::errors::Error> {
+    let mut footer = Footer::new();
+    footer.parse_bytes(untrusted_footer)?;
+    Ok(footer)
+}
+
+/// Picks the key named by the footer's `kid`. The footer is authenticated
+/// along with the token, so a forged key id fails verification.
+fn token_key<'a>(
+    footer: &Footer,
+    keyring: &'a Keyring,
+) -> Result<&'a Key, pasetors::errors::Error> {
+    footer
+        .get_claim("kid")
+        .and_then(|kid| kid.as_str())
+        .and_then(|kid| keyring.get(kid))
+        .ok_or(pasetors::errors::Error::TokenValidation)
+}
+
+/// Publishes the `v4.public` verification keys.
+pub async fn public_keys(keyring: Keyring) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(
+        &serde_json::json!({ "keys": keyring.public_keys() }),
+    ))
+}
+
+/// Builds a session for a personal API key. API key sessions never expire
+/// on their own; they end when the key is revoked.
+pub async fn verify_api_key(key: String, store: &Store) -> Result<Session, handle_errors::Error> {
+    let grant = store
+        .use_api_key(&hash_token(&key))
+        .await?
+        .ok_or(handle_errors::Error::CannotDecryptToken)?;
+
+    Ok(Session {
+        exp: DateTime::<Utc>::MAX_UTC,
+        account_id: grant.account_id,
+        nbf: Utc::now(),
+        jti: format!("api-key-{}", grant.id.0),
+        token_version: 0,
+        scopes: Some(grant.scopes),
     })
 }
 
+/// Accepts either a PASETO access token or a personal API key in the
+/// `Authorization` header, or a browser session cookie. Cookie sessions
+/// need a matching CSRF token on anything but reads.
+pub fn auth(
+    store: Store,
+    keyring: Keyring,
+) -> impl Filter<Extract = (Session,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("Authorization")
+        .and(warp::cookie::optional::<String>(SESSION_COOKIE))
+        .and(warp::method())
+        .and(csrf_tokens())
+        .and_then(
+            move |header: Option<String>,
+                  cookie: Option<String>,
+                  method: Method,
+                  csrf_cookie: Option<String>,
+                  csrf_header: Option<String>| {
+                let store = store.clone();
+                let keyring = keyring.clone();
+                async move {
+                    let session = match (header, cookie) {
+                        (Some(token), _) if token.starts_with(API_KEY_PREFIX) => {
+                            verify_api_key(token, &store).await
+                        }
+                        (Some(token), _) => verify_token(token, &store, &keyring).await,
+                        (None, Some(token)) => {
+                            check_csrf(&method, csrf_cookie.as_deref(), csrf_header.as_deref())
+                                .map_err(warp::reject::custom)?;
+                            verify_token(token, &store, &keyring).await
+                        }
+                        (None, None) => return Err(warp::reject::reject()),
+                    };
+                    match session {
+                        Ok(session) => Ok(session),
+                        Err(_) => Err(warp::reject::reject()),
+                    }
+                }
+            },
+        )
+}
+
 #[cfg(test)]
 mod authentication_tests {
-    use super::{auth, env, issue_token, AccountId};
+    use chrono::{Duration, Utc};
+    use pasetors::{
+        keys::{AsymmetricKeyPair, AsymmetricPublicKey, Generate},
+        paserk::FormatAsPaserk,
+        token::UntrustedToken,
+        version4::V4,
+        Public,
+    };
+    use sqlx::postgres::PgPoolOptions;
+
+    use super::{
+        auth, issue_token, token_footer, verify_token, with_session_cookies, AccountId,
+        ClaimsValidationRules, Keyring, Store, Tokens,
+    };
+
+    fn lazy_store() -> Store {
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/rwd")
+            .unwrap();
+        let store = Store::from_pool(pool);
+        store.cache_token_version(&AccountId(3), 0);
+        store.cache_revoked_tokens(Vec::new());
+        store
+    }
+
+    fn keyring(current: &str, retired: &[&str]) -> Keyring {
+        let keys = vec![
+            "old:RANDOM WORDS WINTER MACINTOSH PC".to_string(),
+            "new:SOME OTHER THIRTY TWO BYTES KEY!".to_string(),
+        ];
+        let retired: Vec<String> = retired.iter().map(|s| s.to_string()).collect();
+        Keyring::new(&keys, Some(current), &retired).unwrap()
+    }
 
     #[tokio::test]
     async fn post_questions_auth() {
-        env::set_var("PASETO_KEY", "RANDOM WORDS WINTER MACINTOSH PC");
-        let token = issue_token(AccountId(3));
+        let keyring = keyring("old", &[]);
+        let token = issue_token(&keyring, AccountId(3), 0);
 
-        let filter = auth();
+        let filter = auth(lazy_store(), keyring);
 
         let res = warp::test::request()
             .header("Authorization", token)
@@ -122,4 +760,158 @@ mod authentication_tests {
 
         assert_eq!(res.await.unwrap().account_id, AccountId(3));
     }
+
+    #[tokio::test]
+    async fn cookie_sessions_need_csrf_token_for_writes() {
+        let keyring = keyring("old", &[]);
+        let token = issue_token(&keyring, AccountId(3), 0);
+        let filter = auth(lazy_store(), keyring);
+        let cookie = format!("qna_session={}; qna_csrf=abc123", token);
+
+        let read = warp::test::request()
+            .method("GET")
+            .header("Cookie", &cookie)
+            .filter(&filter);
+        assert_eq!(read.await.unwrap().account_id, AccountId(3));
+
+        let missing = warp::test::request()
+            .method("POST")
+            .header("Cookie", &cookie)
+            .filter(&filter);
+        assert!(missing.await.is_err());
+
+        let wrong = warp::test::request()
+            .method("POST")
+            .header("Cookie", &cookie)
+            .header("X-CSRF-Token", "abc124")
+            .filter(&filter);
+        assert!(wrong.await.is_err());
+
+        let matching = warp::test::request()
+            .method("POST")
+            .header("Cookie", &cookie)
+            .header("X-CSRF-Token", "abc123")
+            .filter(&filter);
+        assert_eq!(matching.await.unwrap().account_id, AccountId(3));
+    }
+
+    #[tokio::test]
+    async fn header_sessions_need_no_csrf_token() {
+        let keyring = keyring("old", &[]);
+        let token = issue_token(&keyring, AccountId(3), 0);
+        let filter = auth(lazy_store(), keyring);
+
+        let res = warp::test::request()
+            .method("POST")
+            .header("Authorization", token)
+            .filter(&filter);
+        assert_eq!(res.await.unwrap().account_id, AccountId(3));
+    }
+
+    #[test]
+    fn session_cookies_are_http_only_and_strict() {
+        let tokens = Tokens {
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+        };
+        let response = with_session_cookies(warp::reply(), &tokens);
+        let cookies: Vec<&str> = response
+            .headers()
+            .get_all("set-cookie")
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect();
+
+        assert_eq!(cookies.len(), 3);
+        assert!(cookies[0].starts_with("qna_session=access;"));
+        assert!(cookies[1].starts_with("qna_refresh=refresh;"));
+        for cookie in &cookies[..2] {
+            assert!(cookie.contains("HttpOnly"));
+        }
+        assert!(!cookies[2].contains("HttpOnly"));
+        assert!(cookies
+            .iter()
+            .all(|c| c.contains("Secure") && c.contains("SameSite=Strict")));
+    }
+
+    #[tokio::test]
+    async fn tokens_have_unique_ids() {
+        let keyring = keyring("old", &[]);
+        let store = lazy_store();
+
+        let first = verify_token(issue_token(&keyring, AccountId(3), 0), &store, &keyring)
+            .await
+            .unwrap();
+        let second = verify_token(issue_token(&keyring, AccountId(3), 0), &store, &keyring)
+            .await
+            .unwrap();
+
+        assert_ne!(first.jti, second.jti);
+    }
+
+    #[tokio::test]
+    async fn stale_token_version_is_rejected() {
+        let keyring = keyring("old", &[]);
+        let store = lazy_store();
+        let token = issue_token(&keyring, AccountId(3), 0);
+
+        // A password change bumps the stored version.
+        store.cache_token_version(&AccountId(3), 1);
+
+        assert!(verify_token(token, &store, &keyring).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn revoked_tokens_are_rejected() {
+        let keyring = keyring("old", &[]);
+        let store = lazy_store();
+        let token = issue_token(&keyring, AccountId(3), 0);
+        let session = verify_token(token.clone(), &store, &keyring).await.unwrap();
+
+        // A logout on another instance, as seen by the next reload.
+        store.cache_revoked_tokens(vec![(session.jti, Utc::now() + Duration::minutes(15))]);
+
+        assert!(verify_token(token, &store, &keyring).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rotated_keys_keep_tokens_valid_until_retired() {
+        let store = lazy_store();
+        let token = issue_token(&keyring("old", &[]), AccountId(3), 0);
+
+        let rotated = keyring("new", &[]);
+        assert!(verify_token(token.clone(), &store, &rotated).await.is_ok());
+
+        let retired = keyring("new", &["old"]);
+        assert!(verify_token(token, &store, &retired).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn public_tokens_verify_with_published_key() {
+        let pair = AsymmetricKeyPair::<V4>::generate().unwrap();
+        let mut secret = String::new();
+        pair.secret.fmt(&mut secret).unwrap();
+        let keyring = Keyring::new(&[format!("signing:{}", secret)], None, &[]).unwrap();
+        let store = lazy_store();
+
+        let token = issue_token(&keyring, AccountId(3), 0);
+        assert!(token.starts_with("v4.public."));
+        let session = verify_token(token.clone(), &store, &keyring).await.unwrap();
+        assert_eq!(session.account_id, AccountId(3));
+
+        // Another service only needs the published key to verify the token.
+        let published = keyring.public_keys();
+        assert_eq!(published.len(), 1);
+        let public_key = AsymmetricPublicKey::<V4>::try_from(published[0].key.as_str()).unwrap();
+        let untrusted = UntrustedToken::<Public, V4>::try_from(&token).unwrap();
+        let footer = token_footer(untrusted.untrusted_footer()).unwrap();
+        assert!(pasetors::public::verify(
+            &public_key,
+            &untrusted,
+            &ClaimsValidationRules::new(),
+            Some(&footer),
+            None,
+        )
+        .is_ok());
+    }
 }
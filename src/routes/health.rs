@@ -0,0 +1,25 @@
+use serde::Serialize;
+use warp::{Rejection, Reply};
+
+use crate::profanity::{ProfanityHealth, ProfanityService};
+
+#[derive(Serialize, Debug)]
+struct Health {
+    /// `degraded` while a dependency is unavailable.
+    status: &'static str,
+    profanity_filter: ProfanityHealth,
+}
+
+/// Reports the state of the service's dependencies. Always answers 200, so
+/// a degraded dependency doesn't take the service out of a load balancer.
+pub async fn health(profanity: ProfanityService) -> Result<impl Reply, Rejection> {
+    let profanity_filter = profanity.health();
+    let status = match profanity_filter.breaker.state {
+        "closed" => "ok",
+        _ => "degraded",
+    };
+    Ok(warp::reply::json(&Health {
+        status,
+        profanity_filter,
+    }))
+}
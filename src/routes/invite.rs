@@ -0,0 +1,88 @@
+use chrono::Utc;
+use handle_errors::FieldError;
+use rand::random;
+use warp::http::StatusCode;
+use warp::{Rejection, Reply};
+
+use crate::account::Session;
+use crate::routes::authentication::{hash_token, require_admin};
+use crate::store::Store;
+use crate::types::invite::{CreatedInvite, InviteId, NewInvite};
+
+/// Longest an invite can stay valid.
+const MAX_INVITE_DAYS: i64 = 90;
+
+fn field_error(field: &str, message: &str) -> FieldError {
+    FieldError {
+        field: field.to_string(),
+        message: message.to_string(),
+    }
+}
+
+pub async fn add_invite(
+    session: Session,
+    store: Store,
+    new_invite: NewInvite,
+) -> Result<impl Reply, Rejection> {
+    require_admin(&session, &store).await?;
+
+    let mut errors = Vec::new();
+    if new_invite.max_uses < 1 {
+        errors.push(field_error("max_uses", "must be at least 1"));
+    }
+    if !(1..=MAX_INVITE_DAYS).contains(&new_invite.expires_in_days) {
+        errors.push(field_error(
+            "expires_in_days",
+            &format!("must be between 1 and {}", MAX_INVITE_DAYS),
+        ));
+    }
+    if !errors.is_empty() {
+        return Err(warp::reject::custom(handle_errors::Error::ValidationError(
+            errors,
+        )));
+    }
+
+    let code = hex::encode(random::<[u8; 16]>());
+    let expires_at = Utc::now() + chrono::Duration::days(new_invite.expires_in_days);
+    let id = store
+        .add_invite(
+            &hash_token(&code),
+            &session.account_id,
+            new_invite.max_uses,
+            expires_at,
+        )
+        .await?;
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&CreatedInvite {
+            id,
+            code,
+            max_uses: new_invite.max_uses,
+            expires_at,
+        }),
+        StatusCode::CREATED,
+    ))
+}
+
+pub async fn get_invites(session: Session, store: Store) -> Result<impl Reply, Rejection> {
+    require_admin(&session, &store).await?;
+    match store.get_invites().await {
+        Ok(invites) => Ok(warp::reply::json(&invites)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+pub async fn delete_invite(
+    id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl Reply, Rejection> {
+    require_admin(&session, &store).await?;
+    if !store.revoke_invite(&InviteId(id)).await? {
+        return Err(warp::reject::not_found());
+    }
+    Ok(warp::reply::with_status(
+        format!("Invite: {} revoked", id),
+        StatusCode::OK,
+    ))
+}
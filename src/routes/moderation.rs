@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use handle_errors::Error;
+use warp::http::StatusCode;
+use warp::{Rejection, Reply};
+
+use crate::account::Session;
+use crate::routes::authentication::require_moderator;
+use crate::store::Store;
+use crate::types::answer::Answer;
+use crate::types::moderation::{FlaggedPosts, ModerationDecision};
+use crate::types::pagination::{extract_pagination, Pagination};
+use crate::types::question::Question;
+
+/// Lists the fields the profanity filter changed, with their original text.
+pub async fn get_profanity_audits(
+    params: HashMap<String, String>,
+    session: Session,
+    store: Store,
+) -> Result<impl Reply, Rejection> {
+    require_moderator(&session, &store).await?;
+    let mut pagination = Pagination::default();
+    if !params.is_empty() {
+        pagination = extract_pagination(params)?;
+    }
+    match store
+        .get_profanity_audits(pagination.limit, pagination.offset)
+        .await
+    {
+        Ok(audits) => Ok(warp::reply::json(&audits)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+/// Lists the posts flagged for a moderator, oldest first.
+pub async fn get_flagged_posts(
+    params: HashMap<String, String>,
+    session: Session,
+    store: Store,
+) -> Result<impl Reply, Rejection> {
+    require_moderator(&session, &store).await?;
+    let mut pagination = Pagination::default();
+    if !params.is_empty() {
+        pagination = extract_pagination(params)?;
+    }
+    let questions = store
+        .get_flagged_questions(pagination.limit, pagination.offset)
+        .await?;
+    let answers = store
+        .get_flagged_answers(pagination.limit, pagination.offset)
+        .await?;
+    Ok(warp::reply::json(&FlaggedPosts { questions, answers }))
+}
+
+/// Approves or rejects a flagged question.
+pub async fn moderate_question(
+    id: i32,
+    decision: ModerationDecision,
+    session: Session,
+    store: Store,
+) -> Result<impl Reply, Rejection> {
+    require_moderator(&session, &store).await?;
+    let question = match store.get_flagged_question(id).await? {
+        Some(question) => question,
+        None => return Err(warp::reject::custom(Error::QuestionNotFound)),
+    };
+    let moderated = Question {
+        status: decision.status(),
+        ..question.clone()
+    };
+    if !store.moderate_question(&question, moderated).await? {
+        // Edited or moderated since it was read.
+        return Err(warp::reject::custom(Error::QuestionNotFound));
+    }
+    Ok(warp::reply::with_status(
+        format!("Question {}: {}", id, decision.status().as_str()),
+        StatusCode::OK,
+    ))
+}
+
+/// Approves or rejects a flagged answer.
+pub async fn moderate_answer(
+    id: i32,
+    decision: ModerationDecision,
+    session: Session,
+    store: Store,
+) -> Result<impl Reply, Rejection> {
+    require_moderator(&session, &store).await?;
+    let answer = match store.get_flagged_answer(id).await? {
+        Some(answer) => answer,
+        None => return Err(warp::reject::custom(Error::AnswerNotFound)),
+    };
+    let moderated = Answer {
+        status: decision.status(),
+        ..answer.clone()
+    };
+    if !store.moderate_answer(&answer, moderated).await? {
+        return Err(warp::reject::custom(Error::AnswerNotFound));
+    }
+    Ok(warp::reply::with_status(
+        format!("Answer {}: {}", id, decision.status().as_str()),
+        StatusCode::OK,
+    ))
+}
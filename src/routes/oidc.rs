@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use rand::random;
+use warp::{http::Uri, Rejection, Reply};
+
+use crate::account::{Account, Session};
+use crate::config::RegistrationMode;
+use crate::keyring::Keyring;
+use crate::oidc::{OidcClient, PendingLogin};
+use crate::password::PasswordHasher;
+use crate::profanity::ProfanityService;
+use crate::routes::authentication::{finish_first_factor, reject_if_locked, set_cookies};
+use crate::store::Store;
+use crate::validation::validate_email;
+
+/// Cookie tying a pending SSO login to the browser that started it, so a
+/// callback URL handed to someone else can't log them in.
+pub const OIDC_STATE_COOKIE: &str = "qna_oidc_state";
+/// Lifetime of a pending SSO login, as enforced by `take_oidc_state`.
+const OIDC_STATE_TTL_SECONDS: i64 = 10 * 60;
+
+/// Lax rather than Strict, as the provider's redirect back is a cross-site
+/// navigation.
+fn state_cookie(value: &str, max_age: i64) -> String {
+    format!(
+        "{}={}; Max-Age={}; Path=/oidc; Secure; HttpOnly; SameSite=Lax",
+        OIDC_STATE_COOKIE, value, max_age
+    )
+}
+
+/// Starts an SSO login by redirecting to the identity provider. When a
+/// signed-in user starts it, the identity is linked to their account.
+pub async fn login(
+    session: Option<Session>,
+    store: Store,
+    oidc: Option<OidcClient>,
+) -> Result<impl Reply, Rejection> {
+    let oidc = oidc.ok_or_else(warp::reject::not_found)?;
+
+    let state = hex::encode(random::<[u8; 16]>());
+    let login = PendingLogin {
+        code_verifier: hex::encode(random::<[u8; 32]>()),
+        nonce: hex::encode(random::<[u8; 16]>()),
+        link_account_id: session
+            .filter(|session| !session.is_api_key())
+            .map(|session| session.account_id),
+    };
+    store.add_oidc_state(&state, &login).await?;
+
+    let url = oidc.authorization_url(&state, &login).await?;
+    let uri = url
+        .parse::<Uri>()
+        .map_err(|_| handle_errors::Error::OidcError("Invalid redirect URL".to_string()))?;
+    Ok(set_cookies(
+        warp::redirect::found(uri),
+        &[state_cookie(&state, OIDC_STATE_TTL_SECONDS)],
+    ))
+}
+
+/// Completes an SSO login started in the same browser. A new external
+/// identity is linked to the account that started the login, or gets a new
+/// account whose email is checked like on sign-up; it is never linked to an
+/// existing account by email alone. From
+/// there it goes on like a password login: locked accounts are refused and
+/// accounts with 2FA get a challenge instead of tokens.
+#[allow(clippy::too_many_arguments)]
+pub async fn callback(
+    params: HashMap<String, String>,
+    state_cookie_value: Option<String>,
+    remote: Option<SocketAddr>,
+    store: Store,
+    keyring: Keyring,
+    hasher: PasswordHasher,
+    mode: RegistrationMode,
+    profanity: ProfanityService,
+    oidc: Option<OidcClient>,
+) -> Result<impl Reply, Rejection> {
+    let oidc = oidc.ok_or_else(warp::reject::not_found)?;
+    let failed =
+        |reason: &str| warp::reject::custom(handle_errors::Error::OidcError(reason.into()));
+
+    let (code, state) = match (params.get("code"), params.get("state")) {
+        (Some(code), Some(state)) => (code, state),
+        _ => return Err(failed("Missing code or state")),
+    };
+    if state_cookie_value.as_deref() != Some(state.as_str()) {
+        return Err(failed("State does not belong to this browser"));
+    }
+    let login = store
+        .take_oidc_state(state)
+        .await?
+        .ok_or_else(|| failed("Unknown or expired state"))?;
+
+    let user = oidc.user_info(code, &login).await?;
+
+    let account_id = match store.get_identity_account(oidc.issuer(), &user.sub).await? {
+        Some(account_id) => account_id,
+        None => {
+            let account_id = match login.link_account_id {
+                Some(account_id) => account_id,
+                None => {
+                    let email = match (&user.email, user.email_verified) {
+                        (Some(email), true) => validate_email(email)?,
+                        _ => return Err(failed("Provider did not return a verified email")),
+                    };
+                    profanity.check_email(&email)?;
+                    // Taking over an account by email alone would let whoever
+                    // controls the address at the provider in. The owner has
+                    // to log in and start the login to link it.
+                    if store.find_account_id(&email).await?.is_some() {
+                        return Err(failed("Log in to link this identity to your account"));
+                    }
+                    // Only open instances create accounts for new SSO users.
+                    if mode != RegistrationMode::Open {
+                        return Err(warp::reject::custom(
+                            handle_errors::Error::RegistrationClosed,
+                        ));
+                    }
+                    // SSO-only accounts get a random password nobody knows.
+                    let password = hex::encode(random::<[u8; 32]>());
+                    store
+                        .add_account(Account {
+                            id: None,
+                            email,
+                            password: hasher.hash(password.as_bytes()),
+                        })
+                        .await?
+                }
+            };
+            store
+                .add_identity(&account_id, oidc.issuer(), &user.sub)
+                .await?;
+            account_id
+        }
+    };
+
+    let ip = remote.map(|addr| addr.ip().to_string());
+    let account = store.get_account_by_id(&account_id).await?;
+    reject_if_locked(&store, &account.email, ip.as_deref()).await?;
+    let reply =
+        finish_first_factor(&store, &keyring, &account.email, &account_id, ip.as_deref()).await?;
+    Ok(set_cookies(reply, &[state_cookie("", 0)]))
+}
@@ -0,0 +1,170 @@
+use handle_errors::FieldError;
+use warp::http::StatusCode;
+use warp::{Rejection, Reply};
+
+use crate::account::{AccountId, Session};
+use crate::store::Store;
+use crate::types::organization::{MemberRole, NewMember, NewOrganization, OrgRole, OrganizationId};
+use crate::validation::normalize_email;
+
+fn invalid_field(field: &str, message: &str) -> Rejection {
+    warp::reject::custom(handle_errors::Error::ValidationError(vec![FieldError {
+        field: field.to_string(),
+        message: message.to_string(),
+    }]))
+}
+
+/// Returns the caller's role in an organization. Non-members get the same
+/// answer as for an organization that doesn't exist.
+async fn membership(
+    session: &Session,
+    store: &Store,
+    organization_id: &OrganizationId,
+) -> Result<OrgRole, Rejection> {
+    // Organizations can only be managed with a full session.
+    if session.is_api_key() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
+    match store
+        .get_membership(organization_id, &session.account_id)
+        .await?
+    {
+        Some(role) => Ok(role),
+        None => Err(warp::reject::custom(
+            handle_errors::Error::OrganizationNotFound,
+        )),
+    }
+}
+
+async fn require_org_admin(
+    session: &Session,
+    store: &Store,
+    organization_id: &OrganizationId,
+) -> Result<(), Rejection> {
+    if membership(session, store, organization_id).await? != OrgRole::Admin {
+        return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
+    }
+    Ok(())
+}
+
+pub async fn add_organization(
+    session: Session,
+    store: Store,
+    new_organization: NewOrganization,
+) -> Result<impl Reply, Rejection> {
+    if session.is_api_key() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
+    let name = new_organization.name.trim();
+    if name.is_empty() || name.chars().count() > 255 {
+        return Err(invalid_field(
+            "name",
+            "must be between 1 and 255 characters",
+        ));
+    }
+    let organization = store.add_organization(name, &session.account_id).await?;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&organization),
+        StatusCode::CREATED,
+    ))
+}
+
+/// Lists the organizations the caller is a member of.
+pub async fn get_organizations(session: Session, store: Store) -> Result<impl Reply, Rejection> {
+    if session.is_api_key() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
+    match store.get_organizations(&session.account_id).await {
+        Ok(organizations) => Ok(warp::reply::json(&organizations)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+pub async fn get_members(id: i32, session: Session, store: Store) -> Result<impl Reply, Rejection> {
+    let organization_id = OrganizationId(id);
+    membership(&session, &store, &organization_id).await?;
+    match store.get_members(&organization_id).await {
+        Ok(members) => Ok(warp::reply::json(&members)),
+        Err(err) => Err(warp::reject::custom(err)),
+    }
+}
+
+pub async fn add_member(
+    id: i32,
+    session: Session,
+    store: Store,
+    new_member: NewMember,
+) -> Result<impl Reply, Rejection> {
+    let organization_id = OrganizationId(id);
+    require_org_admin(&session, &store, &organization_id).await?;
+
+    let account_id = match store
+        .find_account_id(&normalize_email(&new_member.email))
+        .await?
+    {
+        Some(account_id) => account_id,
+        None => return Err(invalid_field("email", "has no account")),
+    };
+    if !store
+        .add_member(&organization_id, &account_id, new_member.role)
+        .await?
+    {
+        return Err(invalid_field("email", "is already a member"));
+    }
+    Ok(warp::reply::with_status(
+        "Member added",
+        StatusCode::CREATED,
+    ))
+}
+
+pub async fn update_member(
+    id: i32,
+    account_id: i32,
+    session: Session,
+    store: Store,
+    member_role: MemberRole,
+) -> Result<impl Reply, Rejection> {
+    let organization_id = OrganizationId(id);
+    require_org_admin(&session, &store, &organization_id).await?;
+    if !store
+        .set_member_role(&organization_id, &AccountId(account_id), member_role.role)
+        .await?
+    {
+        return Err(invalid_field(
+            "role",
+            "can't be changed for a non-member or the last admin",
+        ));
+    }
+    Ok(warp::reply::json(&"Member updated"))
+}
+
+/// Removes a member. Admins can remove anyone, members only themselves.
+pub async fn delete_member(
+    id: i32,
+    account_id: i32,
+    session: Session,
+    store: Store,
+) -> Result<impl Reply, Rejection> {
+    let organization_id = OrganizationId(id);
+    let account_id = AccountId(account_id);
+    let role = membership(&session, &store, &organization_id).await?;
+    if role != OrgRole::Admin && account_id != session.account_id {
+        return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
+    }
+    if !store.remove_member(&organization_id, &account_id).await? {
+        return Err(invalid_field(
+            "account_id",
+            "is not a member or the last admin",
+        ));
+    }
+    Ok(warp::reply::with_status(
+        format!("Member: {} removed", account_id.0),
+        StatusCode::OK,
+    ))
+}
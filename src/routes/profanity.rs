@@ -0,0 +1,45 @@
+use warp::http::StatusCode;
+use warp::{Rejection, Reply};
+
+use crate::account::Session;
+use crate::moderation::RescanJob;
+use crate::profanity::ProfanityService;
+use crate::routes::authentication::require_admin;
+use crate::store::Store;
+
+/// Lets an admin drop the cached profanity results, so the next check of
+/// any text goes to the filter again.
+pub async fn flush_cache(
+    session: Session,
+    store: Store,
+    profanity: ProfanityService,
+) -> Result<impl Reply, Rejection> {
+    require_admin(&session, &store).await?;
+    let flushed = profanity.flush_cache();
+    Ok(warp::reply::json(&format!(
+        "Profanity cache flushed, {} entries dropped",
+        flushed
+    )))
+}
+
+/// Lets an admin re-run the active filter over all posts, e.g. after the
+/// wordlist changed. Resumes the last re-scan if it failed.
+pub async fn start_rescan(
+    session: Session,
+    store: Store,
+    rescans: RescanJob,
+) -> Result<impl Reply, Rejection> {
+    require_admin(&session, &store).await?;
+    let rescan = rescans.start().await?;
+    Ok(warp::reply::with_status(
+        warp::reply::json(&rescan),
+        StatusCode::ACCEPTED,
+    ))
+}
+
+/// Shows the progress of the last re-scan, if any.
+pub async fn get_rescan(session: Session, store: Store) -> Result<impl Reply, Rejection> {
+    require_admin(&session, &store).await?;
+    let rescan = store.get_latest_rescan().await?;
+    Ok(warp::reply::json(&rescan))
+}
@@ -5,14 +5,19 @@ use warp::http::StatusCode;
 use warp::{Rejection, Reply};
 
 use crate::account::Session;
-use crate::profanity::check_profanity;
+use crate::profanity::ProfanityService;
 use crate::store::Store;
+use crate::types::api_key::Scope;
+use crate::types::moderation::ModerationStatus;
 use crate::types::pagination::{extract_pagination, Pagination};
 use crate::types::question::{NewQuestion, Question};
 
+/// Lists the public questions, plus the private ones of the viewer's
+/// organizations when the request is authenticated.
 #[instrument]
 pub async fn get_questions(
     params: HashMap<String, String>,
+    session: Option<Session>,
     store: Store,
 ) -> Result<impl Reply, Rejection> {
     let mut pagination = Pagination::default();
@@ -22,8 +27,11 @@ pub async fn get_questions(
     }
 
     info!(pagination = false);
+    let viewer = session
+        .filter(|session| session.has_scope(Scope::QuestionsRead))
+        .map(|session| session.account_id);
     match store
-        .get_questions(pagination.limit, pagination.offset)
+        .get_questions(pagination.limit, pagination.offset, viewer.as_ref())
         .await
     {
         Ok(res) => Ok(warp::reply::json(&res)),
@@ -34,58 +42,96 @@ pub async fn get_questions(
 pub async fn add_question(
     session: Session,
     store: Store,
+    profanity: ProfanityService,
     new_question: NewQuestion,
 ) -> Result<impl Reply, Rejection> {
+    if !session.has_scope(Scope::QuestionsWrite) {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
     let account_id = session.account_id;
-    let title = match check_profanity(new_question.title).await {
-        Ok(res) => res,
-        Err(err) => return Err(warp::reject::custom(err)),
-    };
-    let content = match check_profanity(new_question.content).await {
-        Ok(res) => res,
-        Err(err) => return Err(warp::reject::custom(err)),
-    };
+    if let Some(organization_id) = &new_question.organization_id {
+        if store
+            .get_membership(organization_id, &account_id)
+            .await?
+            .is_none()
+        {
+            return Err(warp::reject::custom(
+                handle_errors::Error::OrganizationNotFound,
+            ));
+        }
+    }
+    let checked = profanity
+        .submit_question(new_question.title, new_question.content, new_question.tags)
+        .await?;
+    let status = checked.status;
     let question = NewQuestion {
-        title,
-        content,
-        tags: new_question.tags,
+        title: checked.title,
+        content: checked.content,
+        tags: checked.tags,
+        organization_id: new_question.organization_id,
     };
 
-    if let Err(err) = store.add_question(question, account_id).await {
-        return Err(warp::reject::custom(err));
+    let question = match store.add_question(question, account_id, status).await {
+        Ok(question) => question,
+        Err(err) => return Err(warp::reject::custom(err)),
+    };
+    if !checked.audits.is_empty() {
+        store
+            .add_profanity_audits(Some(&question.id), None, checked.audits)
+            .await?;
+    }
+    match status {
+        ModerationStatus::Approved => {
+            Ok(warp::reply::with_status("Question added", StatusCode::OK))
+        }
+        ModerationStatus::Pending | ModerationStatus::Flagged | ModerationStatus::Rejected => Ok(
+            warp::reply::with_status("Question awaits moderation", StatusCode::ACCEPTED),
+        ),
     }
-    Ok(warp::reply::with_status("Question added", StatusCode::OK))
 }
 
 pub async fn update_question(
     id: i32,
     session: Session,
     store: Store,
+    profanity: ProfanityService,
     question: Question,
 ) -> Result<impl Reply, Rejection> {
+    if !session.has_scope(Scope::QuestionsWrite) {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
     let account_id = session.account_id;
+    if !store.can_view_question(id, &account_id).await? {
+        return Err(warp::reject::custom(handle_errors::Error::QuestionNotFound));
+    }
     if !store.is_question_owner(id, &account_id).await? {
         return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
     }
-    let title = match check_profanity(question.title).await {
-        Ok(res) => res,
-        Err(err) => return Err(warp::reject::custom(err)),
-    };
-    let content = match check_profanity(question.content).await {
-        Ok(res) => res,
-        Err(err) => return Err(warp::reject::custom(err)),
-    };
+    let checked = profanity
+        .submit_question(question.title, question.content, question.tags)
+        .await?;
     let question = Question {
         id: question.id,
-        title,
-        content,
-        tags: question.tags,
+        title: checked.title,
+        content: checked.content,
+        tags: checked.tags,
+        organization_id: None,
+        status: checked.status,
     };
 
     let res = match store.update_question(question, id, account_id).await {
         Ok(res) => res,
         Err(err) => return Err(warp::reject::custom(err)),
     };
+    if !checked.audits.is_empty() {
+        store
+            .add_profanity_audits(Some(&res.id), None, checked.audits)
+            .await?;
+    }
     Ok(warp::reply::json(&res))
 }
 
@@ -94,7 +140,15 @@ pub async fn delete_question(
     session: Session,
     store: Store,
 ) -> Result<impl Reply, Rejection> {
+    if !session.has_scope(Scope::QuestionsWrite) {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
     let account_id = session.account_id;
+    if !store.can_view_question(id, &account_id).await? {
+        return Err(warp::reject::custom(handle_errors::Error::QuestionNotFound));
+    }
     if !store.is_question_owner(id, &account_id).await? {
         return Err(warp::reject::custom(handle_errors::Error::Unauthorized));
     }
@@ -0,0 +1,211 @@
+use std::net::SocketAddr;
+
+use chrono::Utc;
+use handle_errors::FieldError;
+use rand::random;
+use warp::{Rejection, Reply};
+
+use crate::account::{
+    AccountId, ChallengeResponse, RecoveryCodes, Session, TotpEnrollment, TwoFactorCode,
+};
+use crate::keyring::Keyring;
+use crate::lockout::LockoutPolicy;
+use crate::routes::authentication::{
+    complete_login, hash_token, record_failed_login, reject_if_locked, with_session_cookies,
+};
+use crate::store::Store;
+use crate::totp;
+
+/// Wrong codes allowed per login challenge before the password has to be
+/// entered again.
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+/// Number of recovery codes handed out when TOTP is enabled.
+const RECOVERY_CODE_COUNT: usize = 10;
+
+fn invalid_state(message: &str) -> Rejection {
+    warp::reject::custom(handle_errors::Error::ValidationError(vec![FieldError {
+        field: "totp".to_string(),
+        message: message.to_string(),
+    }]))
+}
+
+/// Starts TOTP enrollment with a new secret. It only takes effect once
+/// `enable_totp` has seen a code made with it.
+pub async fn enroll_totp(session: Session, store: Store) -> Result<impl Reply, Rejection> {
+    if session.is_api_key() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
+    let account = store.get_account_by_id(&session.account_id).await?;
+    let secret = totp::generate_secret();
+    if !store.set_totp_secret(&session.account_id, &secret).await? {
+        return Err(invalid_state("is already enabled"));
+    }
+
+    Ok(warp::reply::json(&TotpEnrollment {
+        provisioning_uri: totp::provisioning_uri(&secret, &account.email),
+        secret,
+    }))
+}
+
+/// Confirms an enrollment with a first code, enables TOTP and returns a
+/// fresh set of recovery codes.
+pub async fn enable_totp(
+    session: Session,
+    store: Store,
+    code: TwoFactorCode,
+) -> Result<impl Reply, Rejection> {
+    if session.is_api_key() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
+    let two_factor = store.get_two_factor(&session.account_id).await?;
+    let secret = match two_factor.secret {
+        Some(secret) if !two_factor.enabled => secret,
+        _ => return Err(invalid_state("has no pending enrollment")),
+    };
+    let step = match totp::verify(&secret, &code.code, Utc::now().timestamp()) {
+        Some(step) => step,
+        None => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::InvalidTwoFactorCode,
+            ))
+        }
+    };
+    store.use_totp_step(&session.account_id, step).await?;
+    store.set_totp_enabled(&session.account_id, true).await?;
+
+    let recovery_codes = new_recovery_codes(&store, &session.account_id).await?;
+    Ok(warp::reply::json(&RecoveryCodes { recovery_codes }))
+}
+
+/// Turns TOTP off and drops the recovery codes. Takes a current code, so a
+/// stolen session alone can't remove the second factor.
+pub async fn disable_totp(
+    session: Session,
+    store: Store,
+    code: TwoFactorCode,
+) -> Result<impl Reply, Rejection> {
+    if session.is_api_key() {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InsufficientScope,
+        ));
+    }
+    if !check_second_factor(&store, &session.account_id, &code.code).await? {
+        return Err(warp::reject::custom(
+            handle_errors::Error::InvalidTwoFactorCode,
+        ));
+    }
+    store.set_totp_enabled(&session.account_id, false).await?;
+    store
+        .replace_recovery_codes(&session.account_id, &[])
+        .await?;
+
+    Ok(warp::reply::json(&"Two-factor authentication disabled"))
+}
+
+/// Second step of a login on an account with 2FA. Wrong codes count
+/// towards the lockout of the account like wrong passwords do.
+pub async fn verify_login(
+    store: Store,
+    keyring: Keyring,
+    policy: LockoutPolicy,
+    remote: Option<SocketAddr>,
+    response: ChallengeResponse,
+) -> Result<impl Reply, Rejection> {
+    let ip = remote.map(|addr| addr.ip().to_string());
+    let challenge_hash = hash_token(&response.challenge);
+    let account_id = match store
+        .attempt_login_challenge(&challenge_hash, MAX_CHALLENGE_ATTEMPTS)
+        .await?
+    {
+        Some(account_id) => account_id,
+        None => {
+            return Err(warp::reject::custom(
+                handle_errors::Error::InvalidTwoFactorCode,
+            ))
+        }
+    };
+    let account = store.get_account_by_id(&account_id).await?;
+    reject_if_locked(&store, &account.email, ip.as_deref()).await?;
+
+    if !check_second_factor(&store, &account_id, &response.code).await? {
+        record_failed_login(
+            &store,
+            policy,
+            &account.email,
+            Some(&account_id),
+            ip.as_deref(),
+        )
+        .await?;
+        return Err(warp::reject::custom(
+            handle_errors::Error::InvalidTwoFactorCode,
+        ));
+    }
+    store.delete_login_challenge(&challenge_hash).await?;
+
+    let tokens =
+        complete_login(&store, &keyring, &account.email, &account_id, ip.as_deref()).await?;
+    Ok(with_session_cookies(warp::reply::json(&tokens), &tokens))
+}
+
+/// Checks a TOTP code, or failing that an unused recovery code, for an
+/// account with TOTP enabled. Either is accepted only once.
+async fn check_second_factor(
+    store: &Store,
+    account_id: &AccountId,
+    code: &str,
+) -> Result<bool, handle_errors::Error> {
+    let two_factor = store.get_two_factor(account_id).await?;
+    let secret = match two_factor.secret {
+        Some(secret) if two_factor.enabled => secret,
+        _ => return Ok(false),
+    };
+    if let Some(step) = totp::verify(&secret, code, Utc::now().timestamp()) {
+        return store.use_totp_step(account_id, step).await;
+    }
+    store
+        .use_recovery_code(account_id, &hash_token(&normalize_recovery_code(code)))
+        .await
+}
+
+/// Generates and stores a new set of recovery codes, replacing the old ones.
+async fn new_recovery_codes(
+    store: &Store,
+    account_id: &AccountId,
+) -> Result<Vec<String>, handle_errors::Error> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = hex::encode(random::<[u8; 5]>());
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|code| hash_token(&normalize_recovery_code(code)))
+        .collect();
+    store.replace_recovery_codes(account_id, &hashes).await?;
+    Ok(codes)
+}
+
+/// Recovery codes are typed in by hand, so case and dashes don't matter.
+fn normalize_recovery_code(code: &str) -> String {
+    code.trim()
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod two_factor_tests {
+    use super::normalize_recovery_code;
+
+    #[test]
+    fn recovery_codes_ignore_case_and_dashes() {
+        assert_eq!(normalize_recovery_code(" 0A1B2-C3D4E "), "0a1b2c3d4e");
+        assert_eq!(normalize_recovery_code("0a1b2c3d4e"), "0a1b2c3d4e");
+    }
+}
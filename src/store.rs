@@ -1,45 +1,145 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
 use handle_errors::Error;
 use sqlx::postgres::{PgPool, PgPoolOptions, PgRow};
 use sqlx::Row;
 use tracing::event;
 
-use crate::account::{Account, AccountId};
+use crate::account::{Account, AccountId, LoginAttempt, RefreshToken, Role, TwoFactor};
+use crate::oidc::PendingLogin;
 use crate::types::answer::{Answer, AnswerId, NewAnswer};
+use crate::types::api_key::{parse_scopes, ApiKey, ApiKeyGrant, ApiKeyId, NewApiKey};
+use crate::types::invite::{Invite, InviteId};
+use crate::types::moderation::{
+    ModerationStatus, NewProfanityAudit, ProfanityAudit, Rescan, RescanStatus,
+};
+use crate::types::organization::{Member, OrgRole, Organization, OrganizationId};
 use crate::types::question::{NewQuestion, Question, QuestionId};
 
 #[derive(Debug, Clone)]
 pub struct Store {
     pub connection: PgPool,
+    /// In-memory copy of the `revoked_tokens` denylist, re-read when stale.
+    revoked_tokens: Arc<RwLock<RevokedTokens>>,
+    /// Set while one task re-reads the denylist.
+    reloading_revoked_tokens: Arc<AtomicBool>,
+    /// Cached `accounts.token_version` values with the time they were read.
+    token_versions: Arc<RwLock<HashMap<AccountId, (i32, Instant)>>>,
+}
+
+fn question_from_row(row: PgRow) -> Question {
+    Question {
+        id: QuestionId(row.get("id")),
+        title: row.get("title"),
+        content: row.get("content"),
+        tags: row.get("tags"),
+        organization_id: row
+            .get::<Option<i32>, _>("organization_id")
+            .map(OrganizationId),
+        status: row
+            .get::<String, _>("moderation_status")
+            .parse()
+            .unwrap_or(ModerationStatus::Pending),
+    }
+}
+
+fn answer_from_row(row: PgRow) -> Answer {
+    Answer {
+        id: AnswerId(row.get("id")),
+        content: row.get("content"),
+        question_id: QuestionId(row.get("question_id")),
+        status: row
+            .get::<String, _>("moderation_status")
+            .parse()
+            .unwrap_or(ModerationStatus::Pending),
+    }
+}
+
+fn rescan_from_row(row: PgRow) -> Rescan {
+    Rescan {
+        id: row.get("id"),
+        status: row
+            .get::<String, _>("status")
+            .parse()
+            .unwrap_or(RescanStatus::Failed),
+        total_questions: row.get("total_questions"),
+        total_answers: row.get("total_answers"),
+        questions_scanned: row.get("questions_scanned"),
+        answers_scanned: row.get("answers_scanned"),
+        questions_changed: row.get("questions_changed"),
+        answers_changed: row.get("answers_changed"),
+        last_question_id: row.get("last_question_id"),
+        last_answer_id: row.get("last_answer_id"),
+        error: row.get("error"),
+        started_on: row.get("started_on"),
+        updated_on: row.get("updated_on"),
+    }
+}
+
+/// Revoked token ids with their expiry, and when they were last read.
+#[derive(Debug, Default)]
+struct RevokedTokens {
+    jtis: HashMap<String, DateTime<Utc>>,
+    loaded_at: Option<Instant>,
 }
 
+/// How long the cached denylist is trusted before it is re-read. Bounds how
+/// long a token logged out on another instance is still accepted here.
+const REVOKED_TOKENS_TTL: Duration = Duration::from_secs(30);
+
+/// How often expired entries are dropped from the denylist table.
+const REVOKED_TOKENS_CLEANUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How long a cached token version is trusted before it is re-read. Bounds
+/// how long a session survives a password change made on another instance.
+const TOKEN_VERSION_TTL: Duration = Duration::from_secs(30);
+
 impl Store {
     pub async fn new(url: &str) -> Self {
         let db_pool = match PgPoolOptions::new().max_connections(5).connect(url).await {
             Ok(pool) => pool,
             Err(err) => panic!("Failed to establish DB connection: {}", err),
         };
+        Store::from_pool(db_pool)
+    }
+
+    pub fn from_pool(pool: PgPool) -> Self {
         Store {
-            connection: db_pool,
+            connection: pool,
+            revoked_tokens: Arc::new(RwLock::new(RevokedTokens::default())),
+            reloading_revoked_tokens: Arc::new(AtomicBool::new(false)),
+            token_versions: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
+    /// Returns the questions `viewer` may see: public ones and those of
+    /// organizations they are a member of. Only published questions are
+    /// listed, apart from the viewer's own.
     pub async fn get_questions(
         &self,
         limit: Option<i64>,
         offset: i64,
+        viewer: Option<&AccountId>,
     ) -> Result<Vec<Question>, Error> {
-        let questions =
-            sqlx::query(r#"SELECT id, title, content, tags FROM questions LIMIT $1 OFFSET $2"#)
-                .bind(limit)
-                .bind(offset)
-                .map(|row: PgRow| Question {
-                    id: QuestionId(row.get("id")),
-                    title: row.get("title"),
-                    content: row.get("content"),
-                    tags: row.get("tags"),
-                })
-                .fetch_all(&self.connection)
-                .await;
+        let questions = sqlx::query(
+            r#"SELECT id, title, content, tags, organization_id, moderation_status FROM questions
+            WHERE (organization_id IS NULL
+                OR organization_id IN
+                    (SELECT organization_id FROM organization_members WHERE account_id = $3))
+            AND (moderation_status = 'approved' OR account_id = $3)
+            ORDER BY id
+            LIMIT $1 OFFSET $2"#,
+        )
+        .bind(limit)
+        .bind(offset)
+        .bind(viewer.map(|id| id.0))
+        .map(question_from_row)
+        .fetch_all(&self.connection)
+        .await;
         match questions {
             Ok(questions) => Ok(questions),
             Err(err) => {
@@ -49,26 +149,54 @@ impl Store {
         }
     }
 
+    /// Whether a question exists and `viewer` may see it. Private questions
+    /// of other organizations and pending questions of other authors look
+    /// the same as missing ones.
+    pub async fn can_view_question(
+        &self,
+        question_id: i32,
+        viewer: &AccountId,
+    ) -> Result<bool, Error> {
+        let question = sqlx::query(
+            r#"SELECT id FROM questions
+            WHERE id = $1
+            AND (organization_id IS NULL
+                OR organization_id IN
+                    (SELECT organization_id FROM organization_members WHERE account_id = $2))
+            AND (moderation_status = 'approved' OR account_id = $2)"#,
+        )
+        .bind(question_id)
+        .bind(viewer.0)
+        .fetch_optional(&self.connection)
+        .await;
+        match question {
+            Ok(question) => Ok(question.is_some()),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
     pub async fn add_question(
         &self,
         new_question: NewQuestion,
         account_id: AccountId,
+        status: ModerationStatus,
     ) -> Result<Question, Error> {
         let question = sqlx::query(
-            r#"INSERT INTO questions (title, content, tags, account_id)
-            VALUES  ($1, $2, $3, $4)
-            RETURNING id, title, content, tags"#,
+            r#"INSERT INTO questions
+                (title, content, tags, account_id, organization_id, moderation_status)
+            VALUES  ($1, $2, $3, $4, $5, $6)
+            RETURNING id, title, content, tags, organization_id, moderation_status"#,
         )
         .bind(new_question.title)
         .bind(new_question.content)
         .bind(new_question.tags)
         .bind(account_id.0)
-        .map(|row: PgRow| Question {
-            id: QuestionId(row.get("id")),
-            title: row.get("title"),
-            content: row.get("content"),
-            tags: row.get("tags"),
-        })
+        .bind(new_question.organization_id.map(|id| id.0))
+        .bind(status.as_str())
+        .map(question_from_row)
         .fetch_one(&self.connection)
         .await;
         match question {
@@ -80,6 +208,9 @@ impl Store {
         }
     }
 
+    /// Stores an edit of a question with the status of its check. A flagged
+    /// or rejected question keeps its status, as only a moderator can clear
+    /// it; the edit goes to them for review.
     pub async fn update_question(
         &self,
         question: Question,
@@ -88,21 +219,21 @@ impl Store {
     ) -> Result<Question, Error> {
         let question = sqlx::query(
             r#"UPDATE questions
-            SET title = $1, content = $2, tags = $3
-            WHERE id = $4 AND account_id = $5
-            RETURNING id, title, content, tags"#,
+            SET title = $1, content = $2, tags = $3,
+                moderation_status = CASE
+                    WHEN moderation_status IN ('flagged', 'rejected') THEN moderation_status
+                    ELSE $4
+                END
+            WHERE id = $5 AND account_id = $6
+            RETURNING id, title, content, tags, organization_id, moderation_status"#,
         )
         .bind(question.title)
         .bind(question.content)
         .bind(question.tags)
+        .bind(question.status.as_str())
         .bind(question_id)
         .bind(account_id.0)
-        .map(|row: PgRow| Question {
-            id: QuestionId(row.get("id")),
-            title: row.get("title"),
-            content: row.get("content"),
-            tags: row.get("tags"),
-        })
+        .map(question_from_row)
         .fetch_one(&self.connection)
         .await;
         match question {
@@ -137,20 +268,18 @@ impl Store {
         &self,
         new_answer: NewAnswer,
         account_id: AccountId,
+        status: ModerationStatus,
     ) -> Result<Answer, Error> {
         let answer = sqlx::query(
-            r#"INSERT INTO answers (content, corresponding_question, account_id)
-            VALUES ($1, $2, $3)
-            RETURNING id, content, corresponding_question AS question_id"#,
+            r#"INSERT INTO answers (content, corresponding_question, account_id, moderation_status)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, content, corresponding_question AS question_id, moderation_status"#,
         )
         .bind(new_answer.content)
         .bind(new_answer.question_id.0)
         .bind(account_id.0)
-        .map(|row: PgRow| Answer {
-            id: AnswerId(row.get("id")),
-            content: row.get("content"),
-            question_id: QuestionId(row.get("question_id")),
-        })
+        .bind(status.as_str())
+        .map(answer_from_row)
         .fetch_one(&self.connection)
         .await;
         match answer {
@@ -162,14 +291,238 @@ impl Store {
         }
     }
 
-    pub async fn add_account(&self, account: Account) -> Result<bool, Error> {
-        let result = sqlx::query(r#"INSERT INTO accounts (email, password) VALUES ($1, $2)"#)
-            .bind(account.email)
-            .bind(account.password)
-            .execute(&self.connection)
-            .await;
+    /// Claims the oldest questions waiting for the moderation worker, so
+    /// workers on other instances skip them until `claim_ttl` has passed.
+    pub async fn get_pending_questions(
+        &self,
+        limit: i64,
+        claim_ttl: Duration,
+    ) -> Result<Vec<Question>, Error> {
+        match sqlx::query(
+            r#"UPDATE questions
+            SET moderation_claimed_on = NOW()
+            WHERE id IN (
+                SELECT id FROM questions
+                WHERE moderation_status = 'pending'
+                AND (moderation_claimed_on IS NULL
+                    OR moderation_claimed_on < NOW() - make_interval(secs => $2))
+                ORDER BY id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, title, content, tags, organization_id, moderation_status"#,
+        )
+        .bind(limit)
+        .bind(claim_ttl.as_secs_f64())
+        .map(question_from_row)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(questions) => Ok(questions),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Stores the checked version of a question. Does nothing, and returns
+    /// false, if the question was edited or moderated meanwhile.
+    pub async fn moderate_question(
+        &self,
+        original: &Question,
+        moderated: Question,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"UPDATE questions
+            SET title = $1, content = $2, tags = $3, moderation_status = $4
+            WHERE id = $5 AND moderation_status = $6
+            AND title = $7 AND content = $8 AND tags IS NOT DISTINCT FROM $9"#,
+        )
+        .bind(moderated.title)
+        .bind(moderated.content)
+        .bind(moderated.tags)
+        .bind(moderated.status.as_str())
+        .bind(original.id.0)
+        .bind(original.status.as_str())
+        .bind(&original.title)
+        .bind(&original.content)
+        .bind(&original.tags)
+        .execute(&self.connection)
+        .await;
         match result {
-            Ok(_) => Ok(true),
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Claims the oldest answers waiting for the moderation worker, like
+    /// `get_pending_questions`.
+    pub async fn get_pending_answers(
+        &self,
+        limit: i64,
+        claim_ttl: Duration,
+    ) -> Result<Vec<Answer>, Error> {
+        match sqlx::query(
+            r#"UPDATE answers
+            SET moderation_claimed_on = NOW()
+            WHERE id IN (
+                SELECT id FROM answers
+                WHERE moderation_status = 'pending'
+                AND (moderation_claimed_on IS NULL
+                    OR moderation_claimed_on < NOW() - make_interval(secs => $2))
+                ORDER BY id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, content, corresponding_question AS question_id, moderation_status"#,
+        )
+        .bind(limit)
+        .bind(claim_ttl.as_secs_f64())
+        .map(answer_from_row)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(answers) => Ok(answers),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Stores the checked version of an answer, like `moderate_question`.
+    pub async fn moderate_answer(
+        &self,
+        original: &Answer,
+        moderated: Answer,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"UPDATE answers
+            SET content = $1, moderation_status = $2
+            WHERE id = $3 AND moderation_status = $4 AND content = $5"#,
+        )
+        .bind(moderated.content)
+        .bind(moderated.status.as_str())
+        .bind(original.id.0)
+        .bind(original.status.as_str())
+        .bind(&original.content)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Flagged questions, oldest first, for moderators to review.
+    pub async fn get_flagged_questions(
+        &self,
+        limit: Option<i64>,
+        offset: i64,
+    ) -> Result<Vec<Question>, Error> {
+        match sqlx::query(
+            r#"SELECT id, title, content, tags, organization_id, moderation_status FROM questions
+            WHERE moderation_status = 'flagged'
+            ORDER BY id
+            LIMIT $1 OFFSET $2"#,
+        )
+        .bind(limit)
+        .bind(offset)
+        .map(question_from_row)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(questions) => Ok(questions),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_flagged_question(&self, id: i32) -> Result<Option<Question>, Error> {
+        match sqlx::query(
+            r#"SELECT id, title, content, tags, organization_id, moderation_status FROM questions
+            WHERE id = $1 AND moderation_status = 'flagged'"#,
+        )
+        .bind(id)
+        .map(question_from_row)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(question) => Ok(question),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Flagged answers, like `get_flagged_questions`.
+    pub async fn get_flagged_answers(
+        &self,
+        limit: Option<i64>,
+        offset: i64,
+    ) -> Result<Vec<Answer>, Error> {
+        match sqlx::query(
+            r#"SELECT id, content, corresponding_question AS question_id, moderation_status
+            FROM answers
+            WHERE moderation_status = 'flagged'
+            ORDER BY id
+            LIMIT $1 OFFSET $2"#,
+        )
+        .bind(limit)
+        .bind(offset)
+        .map(answer_from_row)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(answers) => Ok(answers),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_flagged_answer(&self, id: i32) -> Result<Option<Answer>, Error> {
+        match sqlx::query(
+            r#"SELECT id, content, corresponding_question AS question_id, moderation_status
+            FROM answers
+            WHERE id = $1 AND moderation_status = 'flagged'"#,
+        )
+        .bind(id)
+        .map(answer_from_row)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(answer) => Ok(answer),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn add_account(&self, account: Account) -> Result<AccountId, Error> {
+        let result = sqlx::query(
+            r#"INSERT INTO accounts (email, password) VALUES ($1, $2)
+            RETURNING id"#,
+        )
+        .bind(account.email)
+        .bind(account.password)
+        .map(|row: PgRow| AccountId(row.get("id")))
+        .fetch_one(&self.connection)
+        .await;
+        match result {
+            Ok(id) => Ok(id),
             Err(err) => {
                 event!(
                     tracing::Level::ERROR,
@@ -188,14 +541,35 @@ impl Store {
         }
     }
 
-    pub async fn get_account(&self, email: String) -> Result<Account, Error> {
-        let account = sqlx::query(r#"SELECT * FROM accounts WHERE email = $1"#)
+    /// Looks an account up by email, ignoring case, as older accounts were
+    /// stored as typed.
+    pub async fn get_account(&self, email: String) -> Result<Option<Account>, Error> {
+        let account = sqlx::query(r#"SELECT * FROM accounts WHERE lower(email) = lower($1)"#)
             .bind(email)
             .map(|row: PgRow| Account {
                 id: Some(AccountId(row.get("id"))),
                 email: row.get("email"),
                 password: row.get("password"),
             })
+            .fetch_optional(&self.connection)
+            .await;
+        match account {
+            Ok(account) => Ok(account),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_account_by_id(&self, account_id: &AccountId) -> Result<Account, Error> {
+        let account = sqlx::query(r#"SELECT * FROM accounts WHERE id = $1"#)
+            .bind(account_id.0)
+            .map(|row: PgRow| Account {
+                id: Some(AccountId(row.get("id"))),
+                email: row.get("email"),
+                password: row.get("password"),
+            })
             .fetch_one(&self.connection)
             .await;
         match account {
@@ -207,6 +581,90 @@ impl Store {
         }
     }
 
+    /// Stores a new password hash and bumps the token version, which
+    /// invalidates every token issued before the change.
+    pub async fn update_password(
+        &self,
+        account_id: &AccountId,
+        password: String,
+    ) -> Result<i32, Error> {
+        let version = sqlx::query(
+            r#"UPDATE accounts
+            SET password = $1, token_version = token_version + 1
+            WHERE id = $2
+            RETURNING token_version"#,
+        )
+        .bind(password)
+        .bind(account_id.0)
+        .map(|row: PgRow| row.get::<i32, _>("token_version"))
+        .fetch_one(&self.connection)
+        .await;
+        match version {
+            Ok(version) => {
+                self.cache_token_version(account_id, version);
+                Ok(version)
+            }
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Replaces a password hash without touching the token version, for
+    /// upgrading hashes to new argon2 parameters.
+    pub async fn rehash_password(
+        &self,
+        account_id: &AccountId,
+        password: String,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(r#"UPDATE accounts SET password = $1 WHERE id = $2"#)
+            .bind(password)
+            .bind(account_id.0)
+            .execute(&self.connection)
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Returns the current token version of an account, reading from the
+    /// database only when the cached value is missing or stale.
+    pub async fn get_token_version(&self, account_id: &AccountId) -> Result<i32, Error> {
+        if let Some((version, read_at)) = self.token_versions.read().unwrap().get(account_id) {
+            if read_at.elapsed() < TOKEN_VERSION_TTL {
+                return Ok(*version);
+            }
+        }
+
+        let version = sqlx::query(r#"SELECT token_version FROM accounts WHERE id = $1"#)
+            .bind(account_id.0)
+            .map(|row: PgRow| row.get::<i32, _>("token_version"))
+            .fetch_one(&self.connection)
+            .await;
+        match version {
+            Ok(version) => {
+                self.cache_token_version(account_id, version);
+                Ok(version)
+            }
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub fn cache_token_version(&self, account_id: &AccountId, version: i32) {
+        self.token_versions
+            .write()
+            .unwrap()
+            .insert(account_id.clone(), (version, Instant::now()));
+    }
+
     pub async fn is_question_owner(
         &self,
         question_id: i32,
@@ -227,4 +685,1368 @@ impl Store {
             }
         }
     }
+
+    pub async fn add_refresh_token(
+        &self,
+        account_id: &AccountId,
+        token_hash: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"INSERT INTO refresh_tokens (account_id, token_hash, expires_at)
+            VALUES ($1, $2, $3)"#,
+        )
+        .bind(account_id.0)
+        .bind(token_hash)
+        .bind(expires_at)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshToken>, Error> {
+        let token = sqlx::query(
+            r#"SELECT id, account_id, expires_at, revoked FROM refresh_tokens WHERE token_hash = $1"#,
+        )
+        .bind(token_hash)
+        .map(|row: PgRow| RefreshToken {
+            id: row.get("id"),
+            account_id: AccountId(row.get("account_id")),
+            expires_at: row.get("expires_at"),
+            revoked: row.get("revoked"),
+        })
+        .fetch_optional(&self.connection)
+        .await;
+        match token {
+            Ok(token) => Ok(token),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Marks a single refresh token as revoked. Returns `false` if it was
+    /// already revoked, which means the token has been used twice.
+    pub async fn revoke_refresh_token(&self, id: i32) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"UPDATE refresh_tokens SET revoked = TRUE WHERE id = $1 AND revoked = FALSE"#,
+        )
+        .bind(id)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(res) => Ok(res.rows_affected() == 1),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn revoke_refresh_tokens(&self, account_id: &AccountId) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"UPDATE refresh_tokens SET revoked = TRUE WHERE account_id = $1 AND revoked = FALSE"#,
+        )
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Adds an access token id to the denylist until the token expires.
+    pub async fn revoke_token(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2)
+            ON CONFLICT (jti) DO NOTHING"#,
+        )
+        .bind(jti)
+        .bind(expires_at)
+        .execute(&self.connection)
+        .await;
+        if let Err(err) = result {
+            tracing::event!(tracing::Level::ERROR, "{:?}", err);
+            return Err(Error::DataBaseQueryError(err));
+        }
+
+        let now = Utc::now();
+        let mut revoked = self.revoked_tokens.write().unwrap();
+        revoked.jtis.retain(|_, exp| *exp > now);
+        revoked.jtis.insert(jti.to_string(), expires_at);
+        Ok(true)
+    }
+
+    /// Drops denylist entries whose tokens have expired anyway.
+    pub async fn delete_expired_revoked_tokens(&self) -> Result<u64, Error> {
+        match sqlx::query(r#"DELETE FROM revoked_tokens WHERE expires_at < NOW()"#)
+            .execute(&self.connection)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected()),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Drops expired denylist entries now and then, for as long as the
+    /// service runs.
+    pub fn spawn_revoked_tokens_cleanup(&self) {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(REVOKED_TOKENS_CLEANUP_INTERVAL);
+            loop {
+                interval.tick().await;
+                // Errors are logged by the query; the next run tries again.
+                let _ = store.delete_expired_revoked_tokens().await;
+            }
+        });
+    }
+
+    /// Reloads the denylist into memory, replacing the cached copy.
+    pub async fn load_revoked_tokens(&self) -> Result<bool, Error> {
+        let tokens =
+            sqlx::query(r#"SELECT jti, expires_at FROM revoked_tokens WHERE expires_at >= NOW()"#)
+                .map(|row: PgRow| {
+                    (
+                        row.get::<String, _>("jti"),
+                        row.get::<DateTime<Utc>, _>("expires_at"),
+                    )
+                })
+                .fetch_all(&self.connection)
+                .await;
+        match tokens {
+            Ok(tokens) => {
+                self.cache_revoked_tokens(tokens);
+                Ok(true)
+            }
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub fn cache_revoked_tokens(&self, tokens: Vec<(String, DateTime<Utc>)>) {
+        *self.revoked_tokens.write().unwrap() = RevokedTokens {
+            jtis: tokens.into_iter().collect(),
+            loaded_at: Some(Instant::now()),
+        };
+    }
+
+    /// Checks the cached denylist. Once it is stale, one check starts
+    /// re-reading it in the background while all checks keep using the old
+    /// copy, so logouts on other instances are seen within about the TTL.
+    pub fn is_token_revoked(&self, jti: &str) -> bool {
+        let revoked = self.revoked_tokens.read().unwrap();
+        let stale = match revoked.loaded_at {
+            Some(loaded_at) => loaded_at.elapsed() >= REVOKED_TOKENS_TTL,
+            None => true,
+        };
+        if stale && !self.reloading_revoked_tokens.swap(true, Ordering::AcqRel) {
+            let store = self.clone();
+            tokio::spawn(async move {
+                // A failed read is logged, and retried by a later check.
+                let _ = store.load_revoked_tokens().await;
+                store
+                    .reloading_revoked_tokens
+                    .store(false, Ordering::Release);
+            });
+        }
+        revoked.jtis.get(jti).is_some_and(|exp| *exp > Utc::now())
+    }
+
+    pub async fn add_api_key(
+        &self,
+        account_id: &AccountId,
+        new_key: &NewApiKey,
+        prefix: &str,
+        key_hash: &str,
+    ) -> Result<ApiKeyId, Error> {
+        let scopes: Vec<&str> = new_key.scopes.iter().map(|s| s.as_str()).collect();
+        let id = sqlx::query(
+            r#"INSERT INTO api_keys (account_id, name, prefix, key_hash, scopes)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id"#,
+        )
+        .bind(account_id.0)
+        .bind(&new_key.name)
+        .bind(prefix)
+        .bind(key_hash)
+        .bind(scopes)
+        .map(|row: PgRow| ApiKeyId(row.get("id")))
+        .fetch_one(&self.connection)
+        .await;
+        match id {
+            Ok(id) => Ok(id),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_api_keys(&self, account_id: &AccountId) -> Result<Vec<ApiKey>, Error> {
+        let keys = sqlx::query(
+            r#"SELECT id, name, prefix, scopes, created_on, last_used_on
+            FROM api_keys
+            WHERE account_id = $1 AND revoked = FALSE
+            ORDER BY id"#,
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| ApiKey {
+            id: ApiKeyId(row.get("id")),
+            name: row.get("name"),
+            prefix: row.get("prefix"),
+            scopes: parse_scopes(row.get("scopes")),
+            created_on: row.get("created_on"),
+            last_used_on: row.get("last_used_on"),
+        })
+        .fetch_all(&self.connection)
+        .await;
+        match keys {
+            Ok(keys) => Ok(keys),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn revoke_api_key(
+        &self,
+        id: &ApiKeyId,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        let result =
+            sqlx::query(r#"UPDATE api_keys SET revoked = TRUE WHERE id = $1 AND account_id = $2"#)
+                .bind(id.0)
+                .bind(account_id.0)
+                .execute(&self.connection)
+                .await;
+        match result {
+            Ok(res) => Ok(res.rows_affected() == 1),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Looks up an active key by its hash and records that it was used.
+    /// `last_used_on` is only written once a minute to keep busy keys from
+    /// turning every request into a write.
+    pub async fn use_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyGrant>, Error> {
+        let grant = sqlx::query(
+            r#"SELECT id, account_id, scopes FROM api_keys WHERE key_hash = $1 AND revoked = FALSE"#,
+        )
+        .bind(key_hash)
+        .map(|row: PgRow| ApiKeyGrant {
+            id: ApiKeyId(row.get("id")),
+            account_id: AccountId(row.get("account_id")),
+            scopes: parse_scopes(row.get("scopes")),
+        })
+        .fetch_optional(&self.connection)
+        .await;
+        let grant = match grant {
+            Ok(Some(grant)) => grant,
+            Ok(None) => return Ok(None),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                return Err(Error::DataBaseQueryError(err));
+            }
+        };
+
+        let result = sqlx::query(
+            r#"UPDATE api_keys SET last_used_on = NOW()
+            WHERE id = $1
+            AND (last_used_on IS NULL OR last_used_on < NOW() - INTERVAL '1 minute')"#,
+        )
+        .bind(grant.id.0)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(_) => Ok(Some(grant)),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn find_account_id(&self, email: &str) -> Result<Option<AccountId>, Error> {
+        let id = sqlx::query(r#"SELECT id FROM accounts WHERE lower(email) = lower($1)"#)
+            .bind(email)
+            .map(|row: PgRow| AccountId(row.get("id")))
+            .fetch_optional(&self.connection)
+            .await;
+        match id {
+            Ok(id) => Ok(id),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Records a pending login, dropping those that ran out. A signed-in
+    /// account starting the login is kept, to link the new identity to it.
+    pub async fn add_oidc_state(&self, state: &str, login: &PendingLogin) -> Result<bool, Error> {
+        if let Err(err) = sqlx::query(
+            r#"DELETE FROM oidc_login_states WHERE created_on <= NOW() - INTERVAL '10 minutes'"#,
+        )
+        .execute(&self.connection)
+        .await
+        {
+            tracing::event!(tracing::Level::ERROR, "{:?}", err);
+            return Err(Error::DataBaseQueryError(err));
+        }
+
+        let result = sqlx::query(
+            r#"INSERT INTO oidc_login_states (state, code_verifier, nonce, link_account_id)
+            VALUES ($1, $2, $3, $4)"#,
+        )
+        .bind(state)
+        .bind(&login.code_verifier)
+        .bind(&login.nonce)
+        .bind(login.link_account_id.as_ref().map(|id| id.0))
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Consumes a pending login state, if it exists and is less than ten
+    /// minutes old.
+    pub async fn take_oidc_state(&self, state: &str) -> Result<Option<PendingLogin>, Error> {
+        let pending = sqlx::query(
+            r#"DELETE FROM oidc_login_states
+            WHERE state = $1 AND created_on > NOW() - INTERVAL '10 minutes'
+            RETURNING code_verifier, nonce, link_account_id"#,
+        )
+        .bind(state)
+        .map(|row: PgRow| PendingLogin {
+            code_verifier: row.get("code_verifier"),
+            nonce: row.get("nonce"),
+            link_account_id: row.get::<Option<i32>, _>("link_account_id").map(AccountId),
+        })
+        .fetch_optional(&self.connection)
+        .await;
+        match pending {
+            Ok(pending) => Ok(pending),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_identity_account(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<AccountId>, Error> {
+        let id = sqlx::query(
+            r#"SELECT account_id FROM account_identities WHERE issuer = $1 AND subject = $2"#,
+        )
+        .bind(issuer)
+        .bind(subject)
+        .map(|row: PgRow| AccountId(row.get("account_id")))
+        .fetch_optional(&self.connection)
+        .await;
+        match id {
+            Ok(id) => Ok(id),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn add_identity(
+        &self,
+        account_id: &AccountId,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"INSERT INTO account_identities (account_id, issuer, subject) VALUES ($1, $2, $3)"#,
+        )
+        .bind(account_id.0)
+        .bind(issuer)
+        .bind(subject)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_role(&self, account_id: &AccountId) -> Result<Role, Error> {
+        let role = sqlx::query(r#"SELECT role FROM accounts WHERE id = $1"#)
+            .bind(account_id.0)
+            .map(|row: PgRow| row.get::<String, _>("role"))
+            .fetch_one(&self.connection)
+            .await;
+        match role {
+            Ok(role) => Ok(role.parse().unwrap_or(Role::User)),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn add_login_attempt(
+        &self,
+        email: &str,
+        account_id: Option<&AccountId>,
+        ip: Option<&str>,
+        success: bool,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"INSERT INTO login_attempts (email, account_id, ip, success)
+            VALUES ($1, $2, $3, $4)"#,
+        )
+        .bind(email)
+        .bind(account_id.map(|id| id.0))
+        .bind(ip)
+        .bind(success)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_login_attempts(
+        &self,
+        account_id: &AccountId,
+        limit: i64,
+    ) -> Result<Vec<LoginAttempt>, Error> {
+        let attempts = sqlx::query(
+            r#"SELECT ip, success, created_on FROM login_attempts
+            WHERE account_id = $1
+            ORDER BY created_on DESC
+            LIMIT $2"#,
+        )
+        .bind(account_id.0)
+        .bind(limit)
+        .map(|row: PgRow| LoginAttempt {
+            ip: row.get("ip"),
+            success: row.get("success"),
+            created_on: row.get("created_on"),
+        })
+        .fetch_all(&self.connection)
+        .await;
+        match attempts {
+            Ok(attempts) => Ok(attempts),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Returns when the lockout of an email, or of the client IP it is
+    /// tried from, ends, if either is locked.
+    pub async fn get_lockout(
+        &self,
+        email: &str,
+        ip: Option<&str>,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        let locked_until = sqlx::query(
+            r#"SELECT MAX(locked_until) AS locked_until FROM login_lockouts
+            WHERE ((email = $1 AND ip = '') OR (email = '' AND ip = $2))
+            AND locked_until > NOW()"#,
+        )
+        .bind(email)
+        .bind(ip)
+        .map(|row: PgRow| row.get::<Option<DateTime<Utc>>, _>("locked_until"))
+        .fetch_one(&self.connection)
+        .await;
+        match locked_until {
+            Ok(locked_until) => Ok(locked_until),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Counts a failed login and returns the number of consecutive failures.
+    /// The failures of an account are kept under `(email, "")`, those of a
+    /// client IP over all accounts under `("", ip)`. The count starts over
+    /// once `reset_after` has passed since the last failure, or since the
+    /// lockout ended.
+    pub async fn add_login_failure(
+        &self,
+        email: &str,
+        ip: &str,
+        reset_after: Duration,
+    ) -> Result<i32, Error> {
+        let failures = sqlx::query(
+            r#"INSERT INTO login_lockouts (email, ip, failures) VALUES ($1, $2, 1)
+            ON CONFLICT (email, ip) DO UPDATE SET
+                failures = CASE
+                    WHEN GREATEST(login_lockouts.updated_on, login_lockouts.locked_until)
+                        < NOW() - make_interval(secs => $3) THEN 1
+                    ELSE login_lockouts.failures + 1
+                END,
+                locked_until = CASE
+                    WHEN GREATEST(login_lockouts.updated_on, login_lockouts.locked_until)
+                        < NOW() - make_interval(secs => $3) THEN NULL
+                    ELSE login_lockouts.locked_until
+                END,
+                updated_on = NOW()
+            RETURNING failures"#,
+        )
+        .bind(email)
+        .bind(ip)
+        .bind(reset_after.as_secs_f64())
+        .map(|row: PgRow| row.get::<i32, _>("failures"))
+        .fetch_one(&self.connection)
+        .await;
+        match failures {
+            Ok(failures) => Ok(failures),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Locks the account or client IP counted by `add_login_failure`.
+    pub async fn set_lockout(
+        &self,
+        email: &str,
+        ip: &str,
+        locked_until: DateTime<Utc>,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"UPDATE login_lockouts SET locked_until = $1 WHERE email = $2 AND ip = $3"#,
+        )
+        .bind(locked_until)
+        .bind(email)
+        .bind(ip)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Clears the failures and lockout of an account. Client IPs stay
+    /// locked, so logging into an account of one's own doesn't reset them.
+    pub async fn clear_lockout(&self, email: &str) -> Result<bool, Error> {
+        let result = sqlx::query(r#"DELETE FROM login_lockouts WHERE email = $1"#)
+            .bind(email)
+            .execute(&self.connection)
+            .await;
+        match result {
+            Ok(res) => Ok(res.rows_affected() > 0),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_two_factor(&self, account_id: &AccountId) -> Result<TwoFactor, Error> {
+        let two_factor =
+            sqlx::query(r#"SELECT totp_secret, totp_enabled FROM accounts WHERE id = $1"#)
+                .bind(account_id.0)
+                .map(|row: PgRow| TwoFactor {
+                    secret: row.get("totp_secret"),
+                    enabled: row.get("totp_enabled"),
+                })
+                .fetch_one(&self.connection)
+                .await;
+        match two_factor {
+            Ok(two_factor) => Ok(two_factor),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Stores a new, not yet enabled TOTP secret. Returns false if TOTP is
+    /// already enabled on the account.
+    pub async fn set_totp_secret(
+        &self,
+        account_id: &AccountId,
+        secret: &str,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"UPDATE accounts SET totp_secret = $1, totp_last_step = NULL
+            WHERE id = $2 AND totp_enabled = FALSE"#,
+        )
+        .bind(secret)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(res) => Ok(res.rows_affected() == 1),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn set_totp_enabled(
+        &self,
+        account_id: &AccountId,
+        enabled: bool,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"UPDATE accounts
+            SET totp_enabled = $1, totp_secret = CASE WHEN $1 THEN totp_secret END
+            WHERE id = $2"#,
+        )
+        .bind(enabled)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Records the time step of an accepted TOTP code. Returns false if that
+    /// step or a later one was already used, i.e. the code is replayed.
+    pub async fn use_totp_step(&self, account_id: &AccountId, step: i64) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"UPDATE accounts SET totp_last_step = $1
+            WHERE id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)"#,
+        )
+        .bind(step)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(res) => Ok(res.rows_affected() == 1),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Replaces all recovery codes of an account with the given hashes.
+    pub async fn replace_recovery_codes(
+        &self,
+        account_id: &AccountId,
+        code_hashes: &[String],
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(r#"DELETE FROM recovery_codes WHERE account_id = $1"#)
+            .bind(account_id.0)
+            .execute(&self.connection)
+            .await;
+        if let Err(err) = result {
+            tracing::event!(tracing::Level::ERROR, "{:?}", err);
+            return Err(Error::DataBaseQueryError(err));
+        }
+
+        let result = sqlx::query(
+            r#"INSERT INTO recovery_codes (account_id, code_hash)
+            SELECT $1, code_hash FROM UNNEST($2::VARCHAR[]) AS code_hash"#,
+        )
+        .bind(account_id.0)
+        .bind(code_hashes)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Marks an unused recovery code as used. Returns false if there is no
+    /// such code.
+    pub async fn use_recovery_code(
+        &self,
+        account_id: &AccountId,
+        code_hash: &str,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"UPDATE recovery_codes SET used_on = NOW()
+            WHERE account_id = $1 AND code_hash = $2 AND used_on IS NULL"#,
+        )
+        .bind(account_id.0)
+        .bind(code_hash)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(res) => Ok(res.rows_affected() == 1),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn add_login_challenge(
+        &self,
+        challenge_hash: &str,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"INSERT INTO login_challenges (challenge_hash, account_id) VALUES ($1, $2)"#,
+        )
+        .bind(challenge_hash)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Counts an attempt to answer a login challenge and returns its account,
+    /// unless the challenge is unknown, more than five minutes old or out of
+    /// attempts.
+    pub async fn attempt_login_challenge(
+        &self,
+        challenge_hash: &str,
+        max_attempts: i32,
+    ) -> Result<Option<AccountId>, Error> {
+        let account_id = sqlx::query(
+            r#"UPDATE login_challenges SET attempts = attempts + 1
+            WHERE challenge_hash = $1
+            AND created_on > NOW() - INTERVAL '5 minutes'
+            AND attempts < $2
+            RETURNING account_id"#,
+        )
+        .bind(challenge_hash)
+        .bind(max_attempts)
+        .map(|row: PgRow| AccountId(row.get("account_id")))
+        .fetch_optional(&self.connection)
+        .await;
+        match account_id {
+            Ok(account_id) => Ok(account_id),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn delete_login_challenge(&self, challenge_hash: &str) -> Result<bool, Error> {
+        let result = sqlx::query(r#"DELETE FROM login_challenges WHERE challenge_hash = $1"#)
+            .bind(challenge_hash)
+            .execute(&self.connection)
+            .await;
+        match result {
+            Ok(res) => Ok(res.rows_affected() > 0),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn add_invite(
+        &self,
+        code_hash: &str,
+        created_by: &AccountId,
+        max_uses: i32,
+        expires_at: DateTime<Utc>,
+    ) -> Result<InviteId, Error> {
+        let id = sqlx::query(
+            r#"INSERT INTO invites (code_hash, created_by, max_uses, expires_at)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id"#,
+        )
+        .bind(code_hash)
+        .bind(created_by.0)
+        .bind(max_uses)
+        .bind(expires_at)
+        .map(|row: PgRow| InviteId(row.get("id")))
+        .fetch_one(&self.connection)
+        .await;
+        match id {
+            Ok(id) => Ok(id),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_invites(&self) -> Result<Vec<Invite>, Error> {
+        let invites = sqlx::query(
+            r#"SELECT id, max_uses, uses, expires_at, revoked, created_on
+            FROM invites
+            ORDER BY id"#,
+        )
+        .map(|row: PgRow| Invite {
+            id: InviteId(row.get("id")),
+            max_uses: row.get("max_uses"),
+            uses: row.get("uses"),
+            expires_at: row.get("expires_at"),
+            revoked: row.get("revoked"),
+            created_on: row.get("created_on"),
+        })
+        .fetch_all(&self.connection)
+        .await;
+        match invites {
+            Ok(invites) => Ok(invites),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn revoke_invite(&self, id: &InviteId) -> Result<bool, Error> {
+        let result = sqlx::query(r#"UPDATE invites SET revoked = TRUE WHERE id = $1"#)
+            .bind(id.0)
+            .execute(&self.connection)
+            .await;
+        match result {
+            Ok(res) => Ok(res.rows_affected() == 1),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Takes one use of an invite. Returns `None` if the code is unknown,
+    /// revoked, expired or used up.
+    pub async fn claim_invite(&self, code_hash: &str) -> Result<Option<InviteId>, Error> {
+        let id = sqlx::query(
+            r#"UPDATE invites SET uses = uses + 1
+            WHERE code_hash = $1
+            AND revoked = FALSE
+            AND expires_at > NOW()
+            AND uses < max_uses
+            RETURNING id"#,
+        )
+        .bind(code_hash)
+        .map(|row: PgRow| InviteId(row.get("id")))
+        .fetch_optional(&self.connection)
+        .await;
+        match id {
+            Ok(id) => Ok(id),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Gives back a use taken by `claim_invite`, when the sign-up it was
+    /// claimed for failed.
+    pub async fn release_invite(&self, id: &InviteId) -> Result<bool, Error> {
+        let result =
+            sqlx::query(r#"UPDATE invites SET uses = uses - 1 WHERE id = $1 AND uses > 0"#)
+                .bind(id.0)
+                .execute(&self.connection)
+                .await;
+        match result {
+            Ok(res) => Ok(res.rows_affected() == 1),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Creates an organization with its creator as the first admin.
+    pub async fn add_organization(
+        &self,
+        name: &str,
+        account_id: &AccountId,
+    ) -> Result<Organization, Error> {
+        let organization = sqlx::query(
+            r#"WITH organization AS (
+                INSERT INTO organizations (name) VALUES ($1) RETURNING id, name
+            ), member AS (
+                INSERT INTO organization_members (organization_id, account_id, role)
+                SELECT id, $2, 'admin' FROM organization
+            )
+            SELECT id, name FROM organization"#,
+        )
+        .bind(name)
+        .bind(account_id.0)
+        .map(|row: PgRow| Organization {
+            id: OrganizationId(row.get("id")),
+            name: row.get("name"),
+            role: OrgRole::Admin,
+        })
+        .fetch_one(&self.connection)
+        .await;
+        match organization {
+            Ok(organization) => Ok(organization),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_organizations(
+        &self,
+        account_id: &AccountId,
+    ) -> Result<Vec<Organization>, Error> {
+        let organizations = sqlx::query(
+            r#"SELECT o.id, o.name, m.role
+            FROM organizations o
+            JOIN organization_members m ON m.organization_id = o.id
+            WHERE m.account_id = $1
+            ORDER BY o.id"#,
+        )
+        .bind(account_id.0)
+        .map(|row: PgRow| Organization {
+            id: OrganizationId(row.get("id")),
+            name: row.get("name"),
+            role: row
+                .get::<String, _>("role")
+                .parse()
+                .unwrap_or(OrgRole::Member),
+        })
+        .fetch_all(&self.connection)
+        .await;
+        match organizations {
+            Ok(organizations) => Ok(organizations),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Returns the role of an account in an organization, if it is a member.
+    pub async fn get_membership(
+        &self,
+        organization_id: &OrganizationId,
+        account_id: &AccountId,
+    ) -> Result<Option<OrgRole>, Error> {
+        let role = sqlx::query(
+            r#"SELECT role FROM organization_members
+            WHERE organization_id = $1 AND account_id = $2"#,
+        )
+        .bind(organization_id.0)
+        .bind(account_id.0)
+        .map(|row: PgRow| {
+            row.get::<String, _>("role")
+                .parse()
+                .unwrap_or(OrgRole::Member)
+        })
+        .fetch_optional(&self.connection)
+        .await;
+        match role {
+            Ok(role) => Ok(role),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_members(
+        &self,
+        organization_id: &OrganizationId,
+    ) -> Result<Vec<Member>, Error> {
+        let members = sqlx::query(
+            r#"SELECT m.account_id, a.email, m.role
+            FROM organization_members m
+            JOIN accounts a ON a.id = m.account_id
+            WHERE m.organization_id = $1
+            ORDER BY m.created_on"#,
+        )
+        .bind(organization_id.0)
+        .map(|row: PgRow| Member {
+            account_id: AccountId(row.get("account_id")),
+            email: row.get("email"),
+            role: row
+                .get::<String, _>("role")
+                .parse()
+                .unwrap_or(OrgRole::Member),
+        })
+        .fetch_all(&self.connection)
+        .await;
+        match members {
+            Ok(members) => Ok(members),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Adds a member. Returns false if the account already is one.
+    pub async fn add_member(
+        &self,
+        organization_id: &OrganizationId,
+        account_id: &AccountId,
+        role: OrgRole,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"INSERT INTO organization_members (organization_id, account_id, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING"#,
+        )
+        .bind(organization_id.0)
+        .bind(account_id.0)
+        .bind(role.as_str())
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(res) => Ok(res.rows_affected() == 1),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Changes the role of a member. Refuses to demote the last admin and
+    /// returns false then, or if the account isn't a member.
+    pub async fn set_member_role(
+        &self,
+        organization_id: &OrganizationId,
+        account_id: &AccountId,
+        role: OrgRole,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"UPDATE organization_members SET role = $3
+            WHERE organization_id = $1 AND account_id = $2
+            AND ($3 = 'admin' OR role <> 'admin' OR
+                (SELECT COUNT(*) FROM organization_members
+                WHERE organization_id = $1 AND role = 'admin') > 1)"#,
+        )
+        .bind(organization_id.0)
+        .bind(account_id.0)
+        .bind(role.as_str())
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(res) => Ok(res.rows_affected() == 1),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Removes a member. Refuses to remove the last admin and returns false
+    /// then, or if the account isn't a member.
+    pub async fn remove_member(
+        &self,
+        organization_id: &OrganizationId,
+        account_id: &AccountId,
+    ) -> Result<bool, Error> {
+        let result = sqlx::query(
+            r#"DELETE FROM organization_members
+            WHERE organization_id = $1 AND account_id = $2
+            AND (role <> 'admin' OR
+                (SELECT COUNT(*) FROM organization_members
+                WHERE organization_id = $1 AND role = 'admin') > 1)"#,
+        )
+        .bind(organization_id.0)
+        .bind(account_id.0)
+        .execute(&self.connection)
+        .await;
+        match result {
+            Ok(res) => Ok(res.rows_affected() == 1),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Records the original text of fields the profanity filter changed in
+    /// a question or an answer.
+    pub async fn add_profanity_audits(
+        &self,
+        question_id: Option<&QuestionId>,
+        answer_id: Option<&AnswerId>,
+        audits: Vec<NewProfanityAudit>,
+    ) -> Result<bool, Error> {
+        for audit in audits {
+            let result = sqlx::query(
+                r#"INSERT INTO profanity_audits
+                    (question_id, answer_id, field, original, censored, bad_words, bad_word_count)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            )
+            .bind(question_id.map(|id| id.0))
+            .bind(answer_id.map(|id| id.0))
+            .bind(audit.field)
+            .bind(audit.original)
+            .bind(audit.censored)
+            .bind(&audit.bad_words)
+            .bind(audit.bad_words.len() as i32)
+            .execute(&self.connection)
+            .await;
+            if let Err(err) = result {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                return Err(Error::DataBaseQueryError(err));
+            }
+        }
+        Ok(true)
+    }
+
+    /// Lists recorded profanity audits, newest first.
+    pub async fn get_profanity_audits(
+        &self,
+        limit: Option<i64>,
+        offset: i64,
+    ) -> Result<Vec<ProfanityAudit>, Error> {
+        let audits = sqlx::query(
+            r#"SELECT id, question_id, answer_id, field, original, censored, bad_words,
+                bad_word_count, created_on
+            FROM profanity_audits
+            ORDER BY id DESC
+            LIMIT $1 OFFSET $2"#,
+        )
+        .bind(limit)
+        .bind(offset)
+        .map(|row: PgRow| ProfanityAudit {
+            id: row.get("id"),
+            question_id: row.get::<Option<i32>, _>("question_id").map(QuestionId),
+            answer_id: row.get::<Option<i32>, _>("answer_id").map(AnswerId),
+            field: row.get("field"),
+            original: row.get("original"),
+            censored: row.get("censored"),
+            bad_words: row.get("bad_words"),
+            bad_word_count: row.get("bad_word_count"),
+            created_on: row.get("created_on"),
+        })
+        .fetch_all(&self.connection)
+        .await;
+        match audits {
+            Ok(audits) => Ok(audits),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Checked questions with an id above `after`, in id order. Pending
+    /// questions are left to the moderation worker.
+    pub async fn get_questions_after(
+        &self,
+        after: i32,
+        limit: i64,
+    ) -> Result<Vec<Question>, Error> {
+        match sqlx::query(
+            r#"SELECT id, title, content, tags, organization_id, moderation_status FROM questions
+            WHERE id > $1 AND moderation_status <> 'pending'
+            ORDER BY id
+            LIMIT $2"#,
+        )
+        .bind(after)
+        .bind(limit)
+        .map(question_from_row)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(questions) => Ok(questions),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Checked answers with an id above `after`, like `get_questions_after`.
+    pub async fn get_answers_after(&self, after: i32, limit: i64) -> Result<Vec<Answer>, Error> {
+        match sqlx::query(
+            r#"SELECT id, content, corresponding_question AS question_id, moderation_status
+            FROM answers
+            WHERE id > $1 AND moderation_status <> 'pending'
+            ORDER BY id
+            LIMIT $2"#,
+        )
+        .bind(after)
+        .bind(limit)
+        .map(answer_from_row)
+        .fetch_all(&self.connection)
+        .await
+        {
+            Ok(answers) => Ok(answers),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Records a new re-scan, counting the posts it will go through.
+    /// Returns `None` if a re-scan is already running.
+    pub async fn add_rescan(&self) -> Result<Option<Rescan>, Error> {
+        match sqlx::query(
+            r#"INSERT INTO profanity_rescans (total_questions, total_answers)
+            SELECT
+                (SELECT COUNT(*) FROM questions WHERE moderation_status <> 'pending'),
+                (SELECT COUNT(*) FROM answers WHERE moderation_status <> 'pending')
+            ON CONFLICT (status) WHERE status = 'running' DO NOTHING
+            RETURNING id, status, total_questions, total_answers, questions_scanned,
+                answers_scanned, questions_changed, answers_changed, last_question_id,
+                last_answer_id, error, started_on, updated_on"#,
+        )
+        .map(rescan_from_row)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(rescan) => Ok(rescan),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    pub async fn get_latest_rescan(&self) -> Result<Option<Rescan>, Error> {
+        match sqlx::query(
+            r#"SELECT id, status, total_questions, total_answers, questions_scanned,
+                answers_scanned, questions_changed, answers_changed, last_question_id,
+                last_answer_id, error, started_on, updated_on
+            FROM profanity_rescans
+            ORDER BY id DESC
+            LIMIT 1"#,
+        )
+        .map(rescan_from_row)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(rescan) => Ok(rescan),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Sets a failed re-scan running again. Returns `None` if it isn't
+    /// failed anymore or another re-scan is running.
+    pub async fn restart_rescan(&self, id: i32) -> Result<Option<Rescan>, Error> {
+        match sqlx::query(
+            r#"UPDATE profanity_rescans
+            SET status = 'running', error = NULL, updated_on = NOW()
+            WHERE id = $1 AND status = 'failed'
+            RETURNING id, status, total_questions, total_answers, questions_scanned,
+                answers_scanned, questions_changed, answers_changed, last_question_id,
+                last_answer_id, error, started_on, updated_on"#,
+        )
+        .bind(id)
+        .map(rescan_from_row)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(rescan) => Ok(rescan),
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => Ok(None),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Takes over a running re-scan whose progress wasn't saved for
+    /// `stale_after`, as the instance running it stopped. Returns `None` if
+    /// it is still making progress or another instance took it over first.
+    pub async fn claim_rescan(
+        &self,
+        id: i32,
+        stale_after: Duration,
+    ) -> Result<Option<Rescan>, Error> {
+        match sqlx::query(
+            r#"UPDATE profanity_rescans
+            SET updated_on = NOW()
+            WHERE id = $1 AND status = 'running'
+            AND updated_on < NOW() - make_interval(secs => $2)
+            RETURNING id, status, total_questions, total_answers, questions_scanned,
+                answers_scanned, questions_changed, answers_changed, last_question_id,
+                last_answer_id, error, started_on, updated_on"#,
+        )
+        .bind(id)
+        .bind(stale_after.as_secs_f64())
+        .map(rescan_from_row)
+        .fetch_optional(&self.connection)
+        .await
+        {
+            Ok(rescan) => Ok(rescan),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
+
+    /// Saves the status and progress of a re-scan.
+    pub async fn save_rescan(&self, rescan: &Rescan) -> Result<(), Error> {
+        match sqlx::query(
+            r#"UPDATE profanity_rescans
+            SET status = $1, questions_scanned = $2, answers_scanned = $3,
+                questions_changed = $4, answers_changed = $5,
+                last_question_id = $6, last_answer_id = $7, error = $8, updated_on = NOW()
+            WHERE id = $9"#,
+        )
+        .bind(rescan.status.as_str())
+        .bind(rescan.questions_scanned)
+        .bind(rescan.answers_scanned)
+        .bind(rescan.questions_changed)
+        .bind(rescan.answers_changed)
+        .bind(rescan.last_question_id)
+        .bind(rescan.last_answer_id)
+        .bind(&rescan.error)
+        .bind(rescan.id)
+        .execute(&self.connection)
+        .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                tracing::event!(tracing::Level::ERROR, "{:?}", err);
+                Err(Error::DataBaseQueryError(err))
+            }
+        }
+    }
 }
@@ -1,3 +1,7 @@
 pub mod answer;
+pub mod api_key;
+pub mod invite;
+pub mod moderation;
+pub mod organization;
 pub mod pagination;
 pub mod question;
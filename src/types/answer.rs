@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 
+use crate::types::moderation::ModerationStatus;
 use crate::types::question::QuestionId;
 
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
@@ -22,6 +23,7 @@ pub struct Answer {
     pub id: AnswerId,
     pub content: String,
     pub question_id: QuestionId,
+    pub status: ModerationStatus,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
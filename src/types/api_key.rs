@@ -0,0 +1,107 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::account::AccountId;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ApiKeyId(pub i32);
+
+/// Permission granted to a personal API key.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    #[serde(rename = "questions:read")]
+    QuestionsRead,
+    #[serde(rename = "questions:write")]
+    QuestionsWrite,
+    #[serde(rename = "answers:write")]
+    AnswersWrite,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::QuestionsRead => "questions:read",
+            Scope::QuestionsWrite => "questions:write",
+            Scope::AnswersWrite => "answers:write",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "questions:read" => Ok(Scope::QuestionsRead),
+            "questions:write" => Ok(Scope::QuestionsWrite),
+            "answers:write" => Ok(Scope::AnswersWrite),
+            _ => Err(format!("Unknown scope: {}", s)),
+        }
+    }
+}
+
+/// An API key as listed to its owner. The secret is never returned again
+/// after creation.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ApiKey {
+    pub id: ApiKeyId,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub created_on: DateTime<Utc>,
+    pub last_used_on: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// Returned once when a key is created; holds the plaintext key.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CreatedApiKey {
+    pub id: ApiKeyId,
+    pub name: String,
+    pub key: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// What `auth()` needs to know about a presented key.
+#[derive(Debug, Clone)]
+pub struct ApiKeyGrant {
+    pub id: ApiKeyId,
+    pub account_id: AccountId,
+    pub scopes: Vec<Scope>,
+}
+
+pub fn parse_scopes(scopes: Vec<String>) -> Vec<Scope> {
+    scopes.iter().filter_map(|s| s.parse().ok()).collect()
+}
+
+#[cfg(test)]
+mod api_key_tests {
+    use super::{parse_scopes, Scope};
+
+    #[test]
+    fn scopes_round_trip_through_their_names() {
+        let scopes = vec![
+            Scope::QuestionsRead,
+            Scope::QuestionsWrite,
+            Scope::AnswersWrite,
+        ];
+        let names: Vec<String> = scopes.iter().map(|s| s.as_str().to_string()).collect();
+        assert_eq!(parse_scopes(names), scopes);
+
+        let json = serde_json::to_string(&Scope::AnswersWrite).unwrap();
+        assert_eq!(json, "\"answers:write\"");
+    }
+
+    #[test]
+    fn unknown_scopes_are_dropped() {
+        let scopes = parse_scopes(vec!["questions:read".into(), "admin:all".into()]);
+        assert_eq!(scopes, vec![Scope::QuestionsRead]);
+    }
+}
@@ -0,0 +1,43 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InviteId(pub i32);
+
+/// An invite as listed to admins. The code is never returned again after
+/// creation.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Invite {
+    pub id: InviteId,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub expires_at: DateTime<Utc>,
+    pub revoked: bool,
+    pub created_on: DateTime<Utc>,
+}
+
+/// Request to issue an invite. Defaults to a single use within a week.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewInvite {
+    #[serde(default = "default_max_uses")]
+    pub max_uses: i32,
+    #[serde(default = "default_expires_in_days")]
+    pub expires_in_days: i64,
+}
+
+fn default_max_uses() -> i32 {
+    1
+}
+
+fn default_expires_in_days() -> i64 {
+    7
+}
+
+/// Returned once when an invite is created; holds the plaintext code.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CreatedInvite {
+    pub id: InviteId,
+    pub code: String,
+    pub max_uses: i32,
+    pub expires_at: DateTime<Utc>,
+}
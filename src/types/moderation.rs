@@ -0,0 +1,205 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::types::answer::{Answer, AnswerId};
+use crate::types::question::{Question, QuestionId};
+
+/// Whether a post is shown to everyone. Pending posts wait for the
+/// moderation worker and flagged ones for a moderator, who approves or
+/// rejects them. All but approved posts are only shown to their author.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ModerationStatus {
+    #[default]
+    Approved,
+    Pending,
+    Flagged,
+    Rejected,
+}
+
+impl ModerationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModerationStatus::Approved => "approved",
+            ModerationStatus::Pending => "pending",
+            ModerationStatus::Flagged => "flagged",
+            ModerationStatus::Rejected => "rejected",
+        }
+    }
+
+    /// Status of a post checked in parts: rejected if any part is, else
+    /// flagged if any part is, else pending if any part is.
+    pub fn and(self, other: ModerationStatus) -> ModerationStatus {
+        match (self, other) {
+            (ModerationStatus::Rejected, _) | (_, ModerationStatus::Rejected) => {
+                ModerationStatus::Rejected
+            }
+            (ModerationStatus::Flagged, _) | (_, ModerationStatus::Flagged) => {
+                ModerationStatus::Flagged
+            }
+            (ModerationStatus::Pending, _) | (_, ModerationStatus::Pending) => {
+                ModerationStatus::Pending
+            }
+            _ => ModerationStatus::Approved,
+        }
+    }
+}
+
+impl FromStr for ModerationStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "approved" => Ok(ModerationStatus::Approved),
+            "pending" => Ok(ModerationStatus::Pending),
+            "flagged" => Ok(ModerationStatus::Flagged),
+            "rejected" => Ok(ModerationStatus::Rejected),
+            _ => Err(format!("Unknown moderation status: {}", s)),
+        }
+    }
+}
+
+/// A moderator's decision on a flagged post, as given in the route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationDecision {
+    Approve,
+    Reject,
+}
+
+impl ModerationDecision {
+    pub fn status(&self) -> ModerationStatus {
+        match self {
+            ModerationDecision::Approve => ModerationStatus::Approved,
+            ModerationDecision::Reject => ModerationStatus::Rejected,
+        }
+    }
+}
+
+impl FromStr for ModerationDecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "approve" => Ok(ModerationDecision::Approve),
+            "reject" => Ok(ModerationDecision::Reject),
+            _ => Err(format!("Unknown moderation decision: {}", s)),
+        }
+    }
+}
+
+/// Posts waiting for a moderator.
+#[derive(Serialize, Debug, Clone)]
+pub struct FlaggedPosts {
+    pub questions: Vec<Question>,
+    pub answers: Vec<Answer>,
+}
+
+/// State of a re-scan of existing posts. Failed re-scans can be resumed.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RescanStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
+impl RescanStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RescanStatus::Running => "running",
+            RescanStatus::Completed => "completed",
+            RescanStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for RescanStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "running" => Ok(RescanStatus::Running),
+            "completed" => Ok(RescanStatus::Completed),
+            "failed" => Ok(RescanStatus::Failed),
+            _ => Err(format!("Unknown re-scan status: {}", s)),
+        }
+    }
+}
+
+/// Progress of a re-scan. Posts are scanned in id order, questions first,
+/// so the last ids scanned are where it resumes.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Rescan {
+    pub id: i32,
+    pub status: RescanStatus,
+    pub total_questions: i64,
+    pub total_answers: i64,
+    pub questions_scanned: i64,
+    pub answers_scanned: i64,
+    pub questions_changed: i64,
+    pub answers_changed: i64,
+    pub last_question_id: i32,
+    pub last_answer_id: i32,
+    pub error: Option<String>,
+    pub started_on: DateTime<Utc>,
+    pub updated_on: DateTime<Utc>,
+}
+
+/// A field the profanity filter changed, with the text as it was written.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ProfanityAudit {
+    pub id: i32,
+    pub question_id: Option<QuestionId>,
+    pub answer_id: Option<AnswerId>,
+    pub field: String,
+    pub original: String,
+    pub censored: String,
+    pub bad_words: Vec<String>,
+    pub bad_word_count: i32,
+    pub created_on: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewProfanityAudit {
+    pub field: String,
+    pub original: String,
+    pub censored: String,
+    pub bad_words: Vec<String>,
+}
+
+#[cfg(test)]
+mod moderation_tests {
+    use super::{ModerationStatus, RescanStatus};
+
+    #[test]
+    fn statuses_round_trip_through_their_names() {
+        for status in [
+            ModerationStatus::Approved,
+            ModerationStatus::Pending,
+            ModerationStatus::Flagged,
+            ModerationStatus::Rejected,
+        ] {
+            assert_eq!(status.as_str().parse::<ModerationStatus>(), Ok(status));
+        }
+        assert!("hidden".parse::<ModerationStatus>().is_err());
+        for status in [
+            RescanStatus::Running,
+            RescanStatus::Completed,
+            RescanStatus::Failed,
+        ] {
+            assert_eq!(status.as_str().parse::<RescanStatus>(), Ok(status));
+        }
+    }
+
+    #[test]
+    fn flagged_outranks_pending() {
+        use ModerationStatus::*;
+        assert_eq!(Approved.and(Approved), Approved);
+        assert_eq!(Approved.and(Pending), Pending);
+        assert_eq!(Pending.and(Flagged), Flagged);
+        assert_eq!(Flagged.and(Approved), Flagged);
+        assert_eq!(Flagged.and(Rejected), Rejected);
+    }
+}
@@ -0,0 +1,92 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::AccountId;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OrganizationId(pub i32);
+
+/// Role of a member within an organization. Admins manage the members.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrgRole {
+    Admin,
+    Member,
+}
+
+impl OrgRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrgRole::Admin => "admin",
+            OrgRole::Member => "member",
+        }
+    }
+}
+
+impl FromStr for OrgRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "admin" => Ok(OrgRole::Admin),
+            "member" => Ok(OrgRole::Member),
+            _ => Err(format!("Unknown organization role: {}", s)),
+        }
+    }
+}
+
+/// An organization as listed to one of its members, with their role.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Organization {
+    pub id: OrganizationId,
+    pub name: String,
+    pub role: OrgRole,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewOrganization {
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Member {
+    pub account_id: AccountId,
+    pub email: String,
+    pub role: OrgRole,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewMember {
+    pub email: String,
+    #[serde(default = "default_role")]
+    pub role: OrgRole,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MemberRole {
+    pub role: OrgRole,
+}
+
+fn default_role() -> OrgRole {
+    OrgRole::Member
+}
+
+#[cfg(test)]
+mod organization_tests {
+    use super::{NewMember, OrgRole};
+
+    #[test]
+    fn roles_round_trip_through_their_names() {
+        for role in [OrgRole::Admin, OrgRole::Member] {
+            assert_eq!(role.as_str().parse::<OrgRole>(), Ok(role));
+        }
+        assert!("owner".parse::<OrgRole>().is_err());
+    }
+
+    #[test]
+    fn new_members_default_to_member_role() {
+        let member: NewMember = serde_json::from_str(r#"{"email": "jane@example.com"}"#).unwrap();
+        assert_eq!(member.role, OrgRole::Member);
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+use handle_errors::{Error, FieldError};
+
+use crate::account::Account;
+
+/// Shortest password accepted on registration and password change.
+const MIN_PASSWORD_LENGTH: usize = 10;
+/// Longest password accepted, to bound the time spent hashing.
+const MAX_PASSWORD_LENGTH: usize = 128;
+/// Longest email address allowed by RFC 5321.
+const MAX_EMAIL_LENGTH: usize = 254;
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+
+/// Passwords that show up at the top of every breach corpus, one per line.
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+fn common_passwords() -> &'static HashSet<&'static str> {
+    static COMMON: OnceLock<HashSet<&'static str>> = OnceLock::new();
+    COMMON.get_or_init(|| {
+        COMMON_PASSWORDS
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect()
+    })
+}
+
+/// Trims an email and lowercases its domain. The local part is kept as
+/// typed, since mail servers may treat it case-sensitively.
+pub fn normalize_email(email: &str) -> String {
+    let email = email.trim();
+    match email.rsplit_once('@') {
+        Some((local, domain)) => format!("{}@{}", local, domain.to_lowercase()),
+        None => email.to_string(),
+    }
+}
+
+/// Checks the syntax of a normalized email and returns why it is invalid.
+fn check_email(email: &str) -> Option<&'static str> {
+    if email.is_empty() {
+        return Some("must not be empty");
+    }
+    if email.len() > MAX_EMAIL_LENGTH {
+        return Some("is too long");
+    }
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return Some("must contain an @");
+    };
+    if local.is_empty() || local.len() > MAX_LOCAL_PART_LENGTH {
+        return Some("has an invalid local part");
+    }
+    if local.starts_with('.')
+        || local.ends_with('.')
+        || local.contains("..")
+        || !local
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c))
+    {
+        return Some("has an invalid local part");
+    }
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2
+        || labels.iter().any(|label| {
+            label.is_empty()
+                || label.len() > 63
+                || label.starts_with('-')
+                || label.ends_with('-')
+                || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+    {
+        return Some("has an invalid domain");
+    }
+    None
+}
+
+/// Checks a password against the strength policy and returns why it is
+/// rejected.
+fn check_password(password: &str, email: &str) -> Option<&'static str> {
+    let length = password.chars().count();
+    if length < MIN_PASSWORD_LENGTH {
+        return Some("is too short");
+    }
+    if length > MAX_PASSWORD_LENGTH {
+        return Some("is too long");
+    }
+    if common_passwords().contains(password.to_lowercase().as_str()) {
+        return Some("is too common");
+    }
+    if password.eq_ignore_ascii_case(email) {
+        return Some("must not be the email address");
+    }
+    None
+}
+
+fn field_error(field: &str, message: &str) -> FieldError {
+    FieldError {
+        field: field.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// Normalizes the email of a new account and validates it together with the
+/// password. All failing fields are reported at once.
+pub fn validate_registration(account: Account) -> Result<Account, Error> {
+    let email = normalize_email(&account.email);
+    let mut errors = Vec::new();
+    if let Some(message) = check_email(&email) {
+        errors.push(field_error("email", message));
+    }
+    if let Some(message) = check_password(&account.password, &email) {
+        errors.push(field_error("password", message));
+    }
+    if !errors.is_empty() {
+        return Err(Error::ValidationError(errors));
+    }
+    Ok(Account { email, ..account })
+}
+
+/// Normalizes and validates the email of an account created without a
+/// password, e.g. through SSO.
+pub fn validate_email(email: &str) -> Result<String, Error> {
+    let email = normalize_email(email);
+    match check_email(&email) {
+        Some(message) => Err(Error::ValidationError(vec![field_error("email", message)])),
+        None => Ok(email),
+    }
+}
+
+/// Validates the new password of a password change against the strength
+/// policy.
+pub fn validate_new_password(password: &str, email: &str) -> Result<(), Error> {
+    match check_password(password, email) {
+        Some(message) => Err(Error::ValidationError(vec![field_error(
+            "new_password",
+            message,
+        )])),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod validation_tests {
+    use super::{
+        check_email, check_password, normalize_email, validate_email, validate_registration,
+        Account,
+    };
+
+    #[test]
+    fn email_is_trimmed_and_domain_lowercased() {
+        assert_eq!(
+            normalize_email("  Jane.Doe@Example.COM "),
+            "Jane.Doe@example.com"
+        );
+    }
+
+    #[test]
+    fn invalid_emails_are_rejected() {
+        assert!(check_email("").is_some());
+        assert!(check_email("jane").is_some());
+        assert!(check_email("@example.com").is_some());
+        assert!(check_email("jane@localhost").is_some());
+        assert!(check_email("jane@example..com").is_some());
+        assert!(check_email("jane doe@example.com").is_some());
+        assert!(check_email("jane@-example.com").is_some());
+        assert_eq!(check_email("jane.doe+qna@example.com"), None);
+    }
+
+    #[test]
+    fn sso_emails_are_normalized_and_validated() {
+        assert_eq!(
+            validate_email(" Jane@Example.COM").unwrap(),
+            "Jane@example.com"
+        );
+        assert!(validate_email("jane@localhost").is_err());
+    }
+
+    #[test]
+    fn weak_passwords_are_rejected() {
+        assert_eq!(check_password("", "a@b.io"), Some("is too short"));
+        assert_eq!(check_password("short", "a@b.io"), Some("is too short"));
+        assert_eq!(
+            check_password("Password123", "a@b.io"),
+            Some("is too common")
+        );
+        assert_eq!(
+            check_password("jane@example.com", "jane@example.com"),
+            Some("must not be the email address")
+        );
+        assert_eq!(check_password("correct horse battery", "a@b.io"), None);
+    }
+
+    #[test]
+    fn all_failing_fields_are_reported() {
+        let account = Account {
+            id: None,
+            email: "not-an-email".to_string(),
+            password: "".to_string(),
+        };
+        match validate_registration(account) {
+            Err(handle_errors::Error::ValidationError(errors)) => {
+                let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+                assert_eq!(fields, vec!["email", "password"]);
+            }
+            other => panic!("expected validation error, got {:?}", other),
+        }
+    }
+}